@@ -0,0 +1,299 @@
+use super::*;
+
+/// Builds a direct summation simulator whose time step adapts to keep the local error of each
+/// step under `tolerance`, shrinking during close encounters and growing again afterwards.
+///
+/// Steps are taken with the embedded Dormand-Prince 5(4) Runge-Kutta pair; the difference
+/// between the fifth and fourth order solutions estimates the error.
+#[derive(Debug)]
+pub struct AdaptiveFactory<L: ForceLaw = Newtonian> {
+    pub law: L,
+    /// Largest error allowed per step, relative to the size of the positions and velocities.
+    pub tolerance: Float,
+    pub initial_step: Float,
+    pub min_step: Float,
+    pub max_step: Float,
+}
+
+impl<L: ForceLaw + Default> Default for AdaptiveFactory<L> {
+    fn default() -> Self {
+        AdaptiveFactory {
+            law: L::default(),
+            tolerance: 1e-6,
+            initial_step: 1.0,
+            min_step: 1e-6,
+            max_step: 1.0,
+        }
+    }
+}
+
+impl<L: ForceLaw + Clone + 'static> SimFactory for AdaptiveFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        Box::new(AdaptiveSimulator {
+            masses,
+            law: self.law.clone(),
+            fields: ExternalFields::default(),
+            tolerance: self.tolerance,
+            min_step: self.min_step,
+            max_step: self.max_step,
+            next_step: self.initial_step,
+            last_step: 0.0,
+            rejected_steps: 0,
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("Adaptive Step Simulator")
+    }
+}
+
+/// Rows of the Dormand-Prince tableau for the second through seventh stages.
+const DORMAND_PRINCE: [&[Float]; 6] = [
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+    ],
+    &[
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+    ],
+    &[
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+
+/// Difference between the fifth and fourth order weights, for all seven stages.
+const DORMAND_PRINCE_ERROR: [Float; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+/// Rate of change of a body: its velocity and its acceleration.
+type Derivative = (Point, Point);
+
+#[derive(Debug)]
+struct AdaptiveSimulator<L: ForceLaw> {
+    masses: Vec<Mass>,
+    law: L,
+    fields: ExternalFields,
+    tolerance: Float,
+    min_step: Float,
+    max_step: Float,
+    next_step: Float,
+    last_step: Float,
+    rejected_steps: usize,
+}
+
+impl<L: ForceLaw> AdaptiveSimulator<L> {
+    fn derivatives(&self, masses: &[Mass]) -> Vec<Derivative> {
+        accelerations(&self.law, &self.fields, masses)
+            .into_iter()
+            .zip(masses.iter())
+            .map(|(a, x)| (x.velocity, a))
+            .collect()
+    }
+
+    /// The masses `dt` later by the fifth order solution, and the scaled error of that step.
+    fn attempt(&self, dt: Float) -> (Vec<Mass>, Float) {
+        let mut stages = vec![self.derivatives(&self.masses)];
+        let mut next = self.masses.clone();
+        for row in DORMAND_PRINCE.iter() {
+            for (i, x) in next.iter_mut().enumerate() {
+                let (dx, dv) = row
+                    .iter()
+                    .zip(stages.iter())
+                    .fold((Point::ZERO, Point::ZERO), |(dx, dv), (w, k)| {
+                        (dx + k[i].0 * *w, dv + k[i].1 * *w)
+                    });
+                x.position = self.masses[i].position + dx * dt;
+                x.velocity = self.masses[i].velocity + dv * dt;
+            }
+            stages.push(self.derivatives(&next));
+        }
+
+        // the last stage was evaluated at the fifth order solution, which we keep
+        let mut error: Float = 0.0;
+        for (i, x) in next.iter().enumerate() {
+            let (dx, dv) = DORMAND_PRINCE_ERROR
+                .iter()
+                .zip(stages.iter())
+                .fold((Point::ZERO, Point::ZERO), |(dx, dv), (w, k)| {
+                    (dx + k[i].0 * *w, dv + k[i].1 * *w)
+                });
+            let before = &self.masses[i];
+            let position_scale =
+                self.tolerance * (1.0 + before.position.magnitude().max(x.position.magnitude()));
+            let velocity_scale =
+                self.tolerance * (1.0 + before.velocity.magnitude().max(x.velocity.magnitude()));
+            error = error
+                .max((dx * dt).magnitude() / position_scale)
+                .max((dv * dt).magnitude() / velocity_scale);
+        }
+        (next, error)
+    }
+
+    /// How much to scale the step after one with the given `error`.
+    fn step_factor(error: Float) -> Float {
+        if error == 0.0 {
+            5.0
+        } else {
+            (0.9 * error.powf(-0.2)).clamp(0.2, 5.0)
+        }
+    }
+}
+
+impl<L: ForceLaw> Simulator for AdaptiveSimulator<L> {
+    fn step(&mut self) {
+        let mut dt = self.next_step;
+        loop {
+            let (next, error) = self.attempt(dt);
+            if error <= 1.0 || dt <= self.min_step {
+                self.masses = next;
+                self.last_step = dt;
+                self.next_step =
+                    (dt * Self::step_factor(error)).clamp(self.min_step, self.max_step);
+                return;
+            }
+            self.rejected_steps += 1;
+            dt = (dt * Self::step_factor(error)).max(self.min_step);
+        }
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter())
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut())
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        acceleration_of(&self.law, &self.fields, &self.masses, &[], id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.masses.push(mass);
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id)
+    }
+
+    fn time_step(&self) -> Float {
+        self.last_step
+    }
+
+    fn rejected_steps(&self) -> usize {
+        self.rejected_steps
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A light body on an orbit of eccentricity `e` around a heavy one, starting at apocenter.
+    fn eccentric_binary(e: Float) -> Vec<Mass> {
+        let (m1, m2, a) = (1.0, 0.001, 10.0);
+        let total = m1 + m2;
+        let r = a * (1.0 + e);
+        let v = (total * (1.0 - e) / r).sqrt();
+        let heavy = Mass {
+            position: Point(-r * m2 / total, 0.0),
+            velocity: Point(0.0, -v * m2 / total),
+            mass: m1,
+            charge: 0.0,
+            id: 0,
+        };
+        let light = Mass {
+            position: Point(r * m1 / total, 0.0),
+            velocity: Point(0.0, v * m1 / total),
+            mass: m2,
+            charge: 0.0,
+            id: 0,
+        };
+        vec![heavy, light]
+    }
+
+    #[test]
+    fn test_eccentric_binary() {
+        let law = Newtonian::default();
+        let masses = eccentric_binary(0.9);
+        let before = energy(&law, &masses);
+        let period = 2.0 * std::f64::consts::PI * (1000.0 / 1.001 as Float).sqrt();
+        let mut sim = AdaptiveFactory {
+            tolerance: 1e-9,
+            ..AdaptiveFactory::<Newtonian>::default()
+        }
+        .with_masses(masses);
+
+        let mut time = 0.0;
+        let mut smallest = Float::MAX;
+        let mut largest: Float = 0.0;
+        while time < period {
+            sim.step();
+            time += sim.time_step();
+            smallest = smallest.min(sim.time_step());
+            largest = largest.max(sim.time_step());
+        }
+
+        // the step shrinks through pericenter and grows back out to apocenter
+        assert!(smallest < 0.1);
+        assert!(largest == 1.0);
+
+        let masses: Vec<Mass> = sim.mass_iter().cloned().collect();
+        assert!(((energy(&law, &masses) - before) / before).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rejects_oversized_steps() {
+        let mut sim = AdaptiveFactory {
+            initial_step: 100.0,
+            max_step: 100.0,
+            ..AdaptiveFactory::<Newtonian>::default()
+        }
+        .with_masses(eccentric_binary(0.5));
+
+        sim.step();
+
+        assert!(sim.rejected_steps() > 0);
+        assert!(sim.time_step() < 100.0);
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut sim = AdaptiveFactory::<Newtonian>::default()
+            .with_masses(vec![Mass::new_test_particle(Point::ZERO, Point::ZERO)]);
+        sim.add_field(Box::new(Uniform(Point(0.0, -2.0))));
+
+        sim.step();
+
+        // a uniform field is integrated exactly
+        let dt = sim.time_step();
+        let x = sim.mass_iter().next().unwrap();
+        assert!((x.velocity - Point(0.0, -2.0 * dt)).magnitude() < Point::EPSILON);
+        assert!((x.position - Point(0.0, -dt * dt)).magnitude() < Point::EPSILON);
+    }
+}
@@ -0,0 +1,350 @@
+use super::*;
+
+/// Builds a direct summation simulator where every body takes its own power of two fraction of
+/// `max_step`, so a few tight binaries don't hold the rest of the system to tiny steps.
+///
+/// Bodies are integrated with kick-drift-kick leapfrog. Every step advances one whole block of
+/// `max_step`, at the end of which all bodies are synchronized.
+#[derive(Debug)]
+pub struct BlockFactory<L: ForceLaw = Newtonian> {
+    pub law: L,
+    /// Length of a block, the longest step any body takes.
+    pub max_step: Float,
+    /// Bodies never take steps shorter than `max_step / 2^max_level`. Levels past `MAX_LEVEL`
+    /// are taken as `MAX_LEVEL`.
+    pub max_level: u32,
+    /// Roughly how far a body may drift from a straight line during its step.
+    pub accuracy: Float,
+}
+
+impl<L: ForceLaw + Default> Default for BlockFactory<L> {
+    fn default() -> Self {
+        BlockFactory {
+            law: L::default(),
+            max_step: 1.0,
+            max_level: 10,
+            accuracy: 0.01,
+        }
+    }
+}
+
+impl<L: ForceLaw> BlockFactory<L> {
+    /// A body at this level takes about a million steps a block; any deeper and one close
+    /// encounter stalls the whole simulation.
+    pub const MAX_LEVEL: u32 = 20;
+}
+
+impl<L: ForceLaw + Clone + 'static> SimFactory for BlockFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        Box::new(BlockSimulator {
+            levels: vec![0; masses.len()],
+            masses,
+            law: self.law.clone(),
+            fields: ExternalFields::default(),
+            max_step: self.max_step,
+            max_level: self.max_level.min(BlockFactory::<L>::MAX_LEVEL),
+            accuracy: self.accuracy,
+            start: None,
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("Block Time Step Simulator")
+    }
+}
+
+#[derive(Debug)]
+struct BlockSimulator<L: ForceLaw> {
+    masses: Vec<Mass>,
+    /// Each body steps `max_step / 2^level` at a time.
+    levels: Vec<u32>,
+    law: L,
+    fields: ExternalFields,
+    max_step: Float,
+    max_level: u32,
+    accuracy: Float,
+    /// Accelerations found at the end of the last block, which start the next one unless the
+    /// bodies or fields have changed since.
+    start: Option<Vec<Point>>,
+}
+
+impl<L: ForceLaw> BlockSimulator<L> {
+    fn step_length(&self, level: u32) -> Float {
+        self.max_step / (1u64 << level) as Float
+    }
+
+    /// Number of the shortest steps in a step at `level`.
+    fn substeps(&self, level: u32) -> u64 {
+        1 << (self.max_level - level)
+    }
+
+    /// The coarsest level whose step keeps a body under `acceleration` within `accuracy`.
+    fn level_for(&self, acceleration: Point) -> u32 {
+        let a = acceleration.magnitude();
+        if a == 0.0 {
+            return 0;
+        }
+        let wanted = (2.0 * self.accuracy / a).sqrt();
+        let level = (self.max_step / wanted).log2().ceil();
+        level.max(0.0).min(self.max_level as Float) as u32
+    }
+
+    fn kick(&mut self, i: usize, acceleration: Point) {
+        let half = 0.5 * self.step_length(self.levels[i]);
+        self.masses[i].velocity += acceleration * half;
+    }
+}
+
+impl<L: ForceLaw> Simulator for BlockSimulator<L> {
+    fn step(&mut self) {
+        // everyone is synchronized at the start of a block, so all may pick a new level
+        let start = match self.start.take() {
+            Some(start) => start,
+            None => accelerations(&self.law, &self.fields, &self.masses),
+        };
+        for (i, a) in start.into_iter().enumerate() {
+            self.levels[i] = self.level_for(a);
+            self.kick(i, a);
+        }
+
+        // time goes in the shortest steps, but only stops where some body's step ends
+        let shortest = self.step_length(self.max_level);
+        let total = self.substeps(0);
+        let mut end = Vec::with_capacity(self.masses.len());
+        let mut n = 0;
+        while n < total {
+            let next = self
+                .levels
+                .iter()
+                .map(|level| {
+                    let substeps = self.substeps(*level);
+                    (n / substeps + 1) * substeps
+                })
+                .min()
+                .unwrap_or(total);
+            let drift = (next - n) as Float * shortest;
+            for x in self.masses.iter_mut() {
+                x.position += x.velocity * drift;
+            }
+            n = next;
+
+            for i in 0..self.masses.len() {
+                if n % self.substeps(self.levels[i]) != 0 {
+                    continue;
+                }
+                // close this body's step
+                let a = acceleration(&self.law, &self.fields, &self.masses, i);
+                self.kick(i, a);
+                if n == total {
+                    // every body's step ends with the block, so these come in order
+                    end.push(a);
+                    continue;
+                }
+
+                // open the next one, shorter if need be, or one level longer if that lines up
+                let wanted = self.level_for(a);
+                let level = self.levels[i];
+                if wanted > level {
+                    self.levels[i] = wanted;
+                } else if wanted < level && n % self.substeps(level - 1) == 0 {
+                    self.levels[i] = level - 1;
+                }
+                self.kick(i, a);
+            }
+        }
+        self.start = Some(end);
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.start = None;
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter())
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        self.start = None;
+        Box::new(self.masses.iter_mut())
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        acceleration_of(&self.law, &self.fields, &self.masses, &[], id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.start = None;
+        self.masses.push(mass);
+        self.levels.push(0);
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        let index = self.masses.iter().position(|x| x.id == id)?;
+        self.start = None;
+        self.levels.remove(index);
+        Some(self.masses.remove(index))
+    }
+
+    fn time_step(&self) -> Float {
+        self.max_step
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A tight circular binary at the origin and a light body far away on a circular orbit.
+    fn hierarchical() -> Vec<Mass> {
+        let (m, separation, distance): (Float, Float, Float) = (1.0, 1.0, 1000.0);
+        let v_binary = (m / (2.0 * separation)).sqrt();
+        let v_distant = (2.0 * m / distance).sqrt();
+        let star = Mass {
+            position: Point(separation / 2.0, 0.0),
+            velocity: Point(0.0, v_binary),
+            mass: m,
+            charge: 0.0,
+            id: 0,
+        };
+        vec![
+            star,
+            Mass {
+                position: star.position.inverse(),
+                velocity: star.velocity.inverse(),
+                ..star
+            },
+            Mass {
+                position: Point(distance, 0.0),
+                velocity: Point(0.0, v_distant),
+                mass: 1e-6,
+                charge: 0.0,
+                id: 0,
+            },
+        ]
+    }
+
+    fn simulator(masses: Vec<Mass>) -> BlockSimulator<Newtonian> {
+        BlockSimulator {
+            levels: vec![0; masses.len()],
+            masses,
+            law: Newtonian::default(),
+            fields: ExternalFields::default(),
+            max_step: 1.0,
+            max_level: 10,
+            accuracy: 1e-4,
+            start: None,
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut sim = simulator(hierarchical());
+
+        sim.step();
+
+        // the binary steps far more finely than the distant body
+        assert!(sim.levels[0] >= 4);
+        assert!(sim.levels[1] >= 4);
+        assert_eq!(sim.levels[2], 0);
+    }
+
+    #[test]
+    fn test_calm_block_drifts_once() {
+        // bodies feeling nothing all stay at the top level, and drift the block in one go
+        let start = Mass::new_test_particle(Point(0.3, -0.7), Point(0.1, 0.3));
+        let mut sim = simulator(vec![start]);
+        sim.step();
+        assert_eq!(sim.levels[0], 0);
+        assert_eq!(sim.masses[0].position, start.position + start.velocity);
+
+        // and levels too deep for a block's length are held back
+        let mut sim = BlockFactory::<Newtonian> {
+            max_level: 200,
+            ..BlockFactory::default()
+        }
+        .with_masses(hierarchical());
+        sim.step();
+        assert_eq!(sim.len(), 3);
+    }
+
+    #[test]
+    fn test_near_collision_is_bounded() {
+        let body = |x: Float| Mass {
+            position: Point(x, 0.0),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let mut sim = simulator(vec![body(0.0), body(1e-6)]);
+        sim.max_level = BlockFactory::<Newtonian>::MAX_LEVEL;
+
+        // both bodies want far finer steps than the deepest level, which still ends in moments
+        let started = std::time::Instant::now();
+        sim.step();
+        assert!(started.elapsed().as_secs() < 10);
+        assert!(sim
+            .masses
+            .iter()
+            .all(|x| x.position.magnitude().is_finite()));
+
+        // nor does a field strong enough to hold a body at the deepest level all block
+        let mut sim = simulator(vec![Mass::new_test_particle(Point::ZERO, Point::ZERO)]);
+        sim.max_level = BlockFactory::<Newtonian>::MAX_LEVEL;
+        sim.add_field(Box::new(Uniform(Point(1e12, 0.0))));
+        let started = std::time::Instant::now();
+        sim.step();
+        assert!(started.elapsed().as_secs() < 10);
+        assert_eq!(sim.levels[0], sim.max_level);
+    }
+
+    #[test]
+    fn test_block_end_starts_the_next() {
+        let mut sim = simulator(hierarchical());
+        sim.step();
+        let found = accelerations(&sim.law, &sim.fields, &sim.masses);
+        let kept = sim.start.clone().unwrap();
+        for (kept, found) in kept.into_iter().zip(found) {
+            assert!((kept - found).magnitude() <= 1e-12 * found.magnitude());
+        }
+
+        // changing the bodies means finding them again
+        sim.mass_iter_mut().next().unwrap().position += Point(0.5, 0.0);
+        assert!(sim.start.is_none());
+        sim.step();
+        sim.add_field(Box::new(Uniform(Point(0.0, -2.0))));
+        assert!(sim.start.is_none());
+    }
+
+    #[test]
+    fn test_energy_conserved() {
+        let law = Newtonian::default();
+        let mut sim = simulator(hierarchical());
+        let before = energy(&law, &sim.masses);
+
+        for _i in 0..20 {
+            sim.step();
+        }
+
+        let after = energy(&law, &sim.masses);
+        assert!(((after - before) / before).abs() < 1e-4);
+        // the binary stays bound and in place
+        assert!((sim.masses[0].position - sim.masses[1].position).magnitude() < 1.1);
+    }
+
+    #[test]
+    fn test_single_level_is_leapfrog() {
+        let mut sim = simulator(vec![Mass::new_test_particle(Point::ZERO, Point(1.0, 0.0))]);
+        sim.max_level = 0;
+        sim.add_field(Box::new(Uniform(Point(0.0, -2.0))));
+
+        sim.step();
+        sim.step();
+
+        // leapfrog is exact for a uniform field
+        let x = sim.masses[0];
+        assert!((x.velocity - Point(1.0, -4.0)).magnitude() < Point::EPSILON);
+        assert!((x.position - Point(2.0, -4.0)).magnitude() < Point::EPSILON);
+    }
+}
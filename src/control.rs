@@ -0,0 +1,269 @@
+use super::view::*;
+use super::*;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// A simulator shared between the thread stepping it and a viewer drawing it.
+pub type Shared = Arc<RwLock<Box<dyn Simulator>>>;
+
+/// What a viewer asks of the thread running the simulation.
+#[derive(Debug)]
+pub enum Control {
+    Play,
+    Pause,
+    /// Takes one step and pauses.
+    Step,
+    /// Steps a second while playing.
+    Rate(Float),
+    /// Starts over with another simulator, usually one made again from the same seed.
+    Reset(Box<dyn Simulator>),
+}
+
+/// Steps a shared simulator at a steady pace, doing as it is told over a channel in between.
+#[derive(Debug, Clone)]
+pub struct Runner {
+    pub playing: bool,
+    /// Steps a second while playing.
+    pub rate: Float,
+    /// Steps taken since the start or the last reset.
+    pub steps: usize,
+    /// Where the bodies have been, recorded after every step while its length isn't zero.
+    pub trails: Arc<Mutex<Trails>>,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Runner {
+            playing: true,
+            rate: 10.0,
+            steps: 0,
+            trails: Arc::new(Mutex::new(Trails::default())),
+        }
+    }
+}
+
+impl Runner {
+    pub const MIN_RATE: Float = 0.1;
+    pub const MAX_RATE: Float = 1000.0;
+
+    /// Time between steps while playing.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate.clamp(Runner::MIN_RATE, Runner::MAX_RATE))
+    }
+
+    /// Runs `sim` until every sender of `commands` is gone. While paused the thread only waits
+    /// for commands.
+    pub fn run(&mut self, sim: &Shared, commands: Receiver<Control>) {
+        let mut next = Instant::now() + self.interval();
+        loop {
+            let command = if self.playing {
+                let now = Instant::now();
+                if next <= now {
+                    // steps slower than the interval leave no time to wait, so take what has
+                    // been sent before stepping again
+                    match commands.try_recv() {
+                        Ok(command) => command,
+                        Err(TryRecvError::Disconnected) => return,
+                        Err(TryRecvError::Empty) => {
+                            self.step(sim);
+                            // a slow step pushes the next one back rather than bunching them up
+                            next = (next + self.interval()).max(Instant::now());
+                            continue;
+                        }
+                    }
+                } else {
+                    match commands.recv_timeout(next - now) {
+                        Ok(command) => command,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            } else {
+                match commands.recv() {
+                    Ok(command) => command,
+                    Err(_) => return,
+                }
+            };
+            let was_playing = self.playing;
+            self.obey(sim, command);
+            if self.playing && !was_playing {
+                next = Instant::now() + self.interval();
+            }
+        }
+    }
+
+    /// Acts on a single command.
+    pub fn obey(&mut self, sim: &Shared, command: Control) {
+        match command {
+            Control::Play => self.playing = true,
+            Control::Pause => self.playing = false,
+            Control::Step => {
+                self.playing = false;
+                self.step(sim);
+            }
+            Control::Rate(rate) => self.rate = rate.clamp(Runner::MIN_RATE, Runner::MAX_RATE),
+            Control::Reset(fresh) => {
+                if let Ok(mut s) = sim.write() {
+                    *s = fresh;
+                }
+                if let Ok(mut trails) = self.trails.lock() {
+                    trails.clear();
+                }
+                self.steps = 0;
+            }
+        }
+    }
+
+    fn step(&mut self, sim: &Shared) {
+        if let Ok(mut s) = sim.write() {
+            s.step();
+            self.steps += 1;
+            if let Ok(mut trails) = self.trails.lock() {
+                if trails.length > 0 {
+                    let masses: Vec<&Mass> = s.mass_iter().collect();
+                    trails.record(&masses);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+    use std::sync::mpsc::channel;
+
+    fn simulator() -> Box<dyn Simulator> {
+        NoGravityFactory {}.with_masses(vec![Mass {
+            position: Point::ZERO,
+            velocity: Point(1.0, 0.0),
+            mass: 1.0,
+            charge: 0.0,
+            id: 1,
+        }])
+    }
+
+    /// Takes longer over every step than the runner waits between them.
+    #[derive(Debug)]
+    struct Slow(Vec<Mass>);
+
+    impl Simulator for Slow {
+        fn step(&mut self) {
+            std::thread::sleep(Duration::from_millis(20));
+            for m in self.0.iter_mut() {
+                m.position += m.velocity;
+            }
+        }
+        fn add_field(&mut self, _field: Box<dyn ExternalField>) {}
+        fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+            Box::new(self.0.iter())
+        }
+        fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+            Box::new(self.0.iter_mut())
+        }
+        fn add_mass(&mut self, mass: Mass) {
+            self.0.push(mass);
+        }
+        fn remove_mass(&mut self, _id: u64) -> Option<Mass> {
+            None
+        }
+    }
+
+    fn position(sim: &Shared) -> Point {
+        sim.read().unwrap().find_mass(1).unwrap().position
+    }
+
+    #[test]
+    fn test_obey() {
+        let sim: Shared = Arc::new(RwLock::new(simulator()));
+        let mut runner = Runner::default();
+        runner.obey(&sim, Control::Step);
+        runner.obey(&sim, Control::Step);
+        assert!(!runner.playing);
+        assert_eq!(runner.steps, 2);
+        assert_eq!(position(&sim), Point(2.0, 0.0));
+
+        runner.obey(&sim, Control::Rate(1e9));
+        assert_eq!(runner.rate, Runner::MAX_RATE);
+        runner.obey(&sim, Control::Play);
+        assert!(runner.playing);
+
+        runner.trails.lock().unwrap().length = 5;
+        runner.obey(&sim, Control::Step);
+        runner.obey(&sim, Control::Step);
+        let trail: Vec<Point> = runner
+            .trails
+            .lock()
+            .unwrap()
+            .trail(1)
+            .map(|(p, _)| p)
+            .collect();
+        assert_eq!(trail, vec![Point(3.0, 0.0), Point(4.0, 0.0)]);
+
+        runner.obey(&sim, Control::Reset(simulator()));
+        assert_eq!(runner.steps, 0);
+        assert_eq!(position(&sim), Point::ZERO);
+        assert_eq!(runner.trails.lock().unwrap().trail(1).count(), 0);
+    }
+
+    #[test]
+    fn test_run() {
+        let sim: Shared = Arc::new(RwLock::new(simulator()));
+        let (commands, received) = channel();
+        let shared = sim.clone();
+        let thread = std::thread::spawn(move || {
+            let mut runner = Runner {
+                playing: false,
+                ..Runner::default()
+            };
+            runner.run(&shared, received);
+            runner
+        });
+
+        // paused, it steps only when asked
+        commands.send(Control::Step).unwrap();
+        commands.send(Control::Step).unwrap();
+        commands.send(Control::Rate(Runner::MAX_RATE)).unwrap();
+        commands.send(Control::Play).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        commands.send(Control::Pause).unwrap();
+        drop(commands);
+        let runner = thread.join().unwrap();
+
+        assert!(!runner.playing);
+        assert!(runner.steps > 2);
+        assert_eq!(position(&sim), Point(runner.steps as Float, 0.0));
+    }
+
+    #[test]
+    fn test_slow_steps() {
+        let masses = simulator().mass_iter().cloned().collect();
+        let sim: Shared = Arc::new(RwLock::new(Box::new(Slow(masses))));
+        let (commands, received) = channel();
+        let shared = sim.clone();
+        let thread = std::thread::spawn(move || {
+            let mut runner = Runner {
+                rate: Runner::MAX_RATE,
+                ..Runner::default()
+            };
+            runner.run(&shared, received);
+            runner
+        });
+        let steps = || position(&sim).0 as usize;
+
+        std::thread::sleep(Duration::from_millis(100));
+        commands.send(Control::Pause).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let paused = steps();
+        assert!(paused > 0);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(steps(), paused);
+
+        drop(commands);
+        let runner = thread.join().unwrap();
+        assert!(!runner.playing);
+        assert_eq!(runner.steps, paused);
+    }
+}
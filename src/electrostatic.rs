@@ -0,0 +1,306 @@
+use super::*;
+
+/// Builds a simulator of charged bodies, where every pair interacts directly through the
+/// `electric` law and, optionally, through `gravity` as well.
+#[derive(Debug, Default)]
+pub struct ElectrostaticFactory<E: ForceLaw = Coulomb, G: ForceLaw = Newtonian> {
+    pub electric: E,
+    pub gravity: Option<G>,
+}
+
+impl<E, G> SimFactory for ElectrostaticFactory<E, G>
+where
+    E: ForceLaw + Clone + 'static,
+    G: ForceLaw + Clone + 'static,
+{
+    fn new(&self, count: usize) -> Box<dyn Simulator> {
+        self.with_masses((0..count).map(|_| Mass::new_random_charged()).collect())
+    }
+
+    fn new_seeded(&self, count: usize, seed: u64) -> Box<dyn Simulator> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.with_masses(
+            (0..count)
+                .map(|_| Mass::new_random_charged_from(&mut rng))
+                .collect(),
+        )
+    }
+
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        let (masses, tests) = masses.into_iter().partition(|m| !m.is_test_particle());
+        Box::new(ElectrostaticSimulator {
+            masses,
+            tests,
+            electric: self.electric.clone(),
+            gravity: self.gravity.clone(),
+            fields: ExternalFields::default(),
+        })
+    }
+
+    fn name(&self) -> String {
+        match self.gravity {
+            Some(_) => String::from("Electrostatic Simulator with Gravity"),
+            None => String::from("Electrostatic Simulator"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ElectrostaticSimulator<E: ForceLaw, G: ForceLaw> {
+    masses: Vec<Mass>,
+    tests: Vec<Mass>,
+    electric: E,
+    gravity: Option<G>,
+    fields: ExternalFields,
+}
+
+impl<E: ForceLaw, G: ForceLaw> ElectrostaticSimulator<E, G> {
+    fn force_between(&self, mass: &Mass, source: &Mass) -> Point {
+        let mut force = self.electric.force_between(mass, source);
+        if let Some(ref gravity) = self.gravity {
+            force += gravity.force_between(mass, source);
+        }
+        force
+    }
+
+    /// Acceleration of a test particle at `position`. Test particles probe the bodies as a unit
+    /// charge of unit mass would, pulling and pushing nothing themselves.
+    fn probe(&self, position: Point) -> Point {
+        let mut acceleration = self.fields.acceleration(position);
+        for source in self.masses.iter() {
+            acceleration += self.electric.test_acceleration(position, source);
+            if let Some(ref gravity) = self.gravity {
+                acceleration += gravity.test_acceleration(position, source);
+            }
+        }
+        acceleration
+    }
+}
+
+impl<E: ForceLaw, G: ForceLaw> Simulator for ElectrostaticSimulator<E, G> {
+    fn step(&mut self) {
+        let probed: Vec<Point> = self.tests.iter().map(|t| self.probe(t.position)).collect();
+        for (t, a) in self.tests.iter_mut().zip(probed) {
+            t.velocity += a;
+            t.position += t.velocity;
+        }
+
+        // every pair, once; each force is applied equally and oppositely
+        let mut forces = vec![Point::ZERO; self.masses.len()];
+        for i in 0..self.masses.len() {
+            for j in (i + 1)..self.masses.len() {
+                let f = self.force_between(&self.masses[i], &self.masses[j]);
+                forces[i] += f;
+                forces[j] -= f;
+            }
+        }
+
+        for (x, force) in self.masses.iter_mut().zip(forces) {
+            x.velocity += force / x.mass + self.fields.acceleration(x.position);
+            x.position += x.velocity;
+        }
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter().chain(self.tests.iter()))
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut().chain(self.tests.iter_mut()))
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        // like other engines, only one with gravity has an acceleration to show
+        self.gravity.as_ref()?;
+        let i = match self.masses.iter().position(|m| m.id == id) {
+            Some(i) => i,
+            None => {
+                let t = self.tests.iter().find(|t| t.id == id)?;
+                return Some(self.probe(t.position));
+            }
+        };
+        let x = &self.masses[i];
+        let force = self
+            .masses
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(Point::ZERO, |f, (_, y)| f + self.force_between(x, y));
+        Some(force / x.mass + self.fields.acceleration(x.position))
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        if mass.is_test_particle() {
+            self.tests.push(mass);
+        } else {
+            self.masses.push(mass);
+        }
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id).or_else(|| remove_by_id(&mut self.tests, id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pair(charge1: Float, charge2: Float) -> Vec<Mass> {
+        let test_mass1 = Mass {
+            position: Point(-1.0, 0.0),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: charge1,
+            id: 0,
+        };
+        let test_mass2 = Mass {
+            position: Point(1.0, 0.0),
+            charge: charge2,
+            id: 0,
+            ..test_mass1
+        };
+        vec![test_mass1, test_mass2]
+    }
+
+    #[test]
+    fn test_like_charges_repel() {
+        let mut sim = ElectrostaticSimulator {
+            masses: pair(1.0, 1.0),
+            tests: Vec::new(),
+            electric: Coulomb::default(),
+            gravity: None::<Newtonian>,
+            fields: ExternalFields::default(),
+        };
+
+        sim.step();
+
+        assert!(sim.masses[0].velocity.0 < 0.0);
+        assert!(sim.masses[1].velocity.0 > 0.0);
+        assert!(sim.masses[0].position.1 == 0.0);
+        assert!(sim.masses[1].position.1 == 0.0);
+    }
+
+    #[test]
+    fn test_opposite_charges_attract() {
+        let mut sim = ElectrostaticSimulator {
+            masses: pair(1.0, -1.0),
+            tests: Vec::new(),
+            electric: Coulomb::default(),
+            gravity: None::<Newtonian>,
+            fields: ExternalFields::default(),
+        };
+
+        sim.step();
+
+        assert!(sim.masses[0].velocity.0 > 0.0);
+        assert!(sim.masses[1].velocity.0 < 0.0);
+    }
+
+    #[test]
+    fn test_gravity_combined() {
+        // gravity just outweighs the repulsion of these charges
+        let mut masses = pair(1.0, 1.0);
+        masses[0].mass = 2.0;
+        masses[1].mass = 1.0;
+        let mut sim = ElectrostaticSimulator {
+            masses,
+            tests: Vec::new(),
+            electric: Coulomb::default(),
+            gravity: Some(Newtonian {
+                g: 0.75,
+                ..Newtonian::default()
+            }),
+            fields: ExternalFields::default(),
+        };
+
+        sim.step();
+
+        assert!(sim.masses[0].velocity.0 > 0.0);
+        assert!(sim.masses[1].velocity.0 < 0.0);
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let factory = ElectrostaticFactory {
+            electric: Coulomb::default(),
+            gravity: Some(Newtonian::default()),
+        };
+        let mut masses = pair(1.0, -1.0);
+        masses[0].id = 1;
+        masses[1].id = 2;
+        masses.push(Mass {
+            id: 3,
+            ..Mass::new_test_particle(Point(0.5, 2.0), Point::ZERO)
+        });
+        let mut sim = factory.with_masses(masses);
+        let expected: Vec<Point> = (1..=3).map(|id| sim.acceleration(id).unwrap()).collect();
+
+        // from rest, one step's change in velocity is the acceleration
+        sim.step();
+        for (id, a) in (1..=3).zip(expected) {
+            assert_eq!(sim.find_mass(id).unwrap().velocity, a);
+        }
+        assert!(ElectrostaticFactory::<Coulomb, Newtonian>::default()
+            .with_masses(pair(1.0, -1.0))
+            .acceleration(0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_particles_probe_charges() {
+        let mut masses = pair(1.0, -1.0);
+        masses.push(Mass::new_test_particle(Point(-2.0, 0.0), Point::ZERO));
+        masses.push(Mass::new_test_particle(Point(2.0, 0.0), Point::ZERO));
+        let mut sim = ElectrostaticFactory {
+            electric: Coulomb::default(),
+            gravity: None::<Newtonian>,
+        }
+        .with_masses(masses);
+        sim.step();
+
+        // on either side, the field along the pair's axis points the way of its dipole, from the
+        // negative charge to the positive one, and the probes move neither body
+        let probes: Vec<Mass> = sim.mass_iter().skip(2).cloned().collect();
+        assert!(probes[0].velocity.0 < 0.0);
+        assert!(probes[1].velocity.0 < 0.0);
+        let bodies: Vec<Mass> = sim.mass_iter().take(2).cloned().collect();
+        assert_eq!(bodies[0].velocity.0, -bodies[1].velocity.0);
+    }
+
+    #[test]
+    fn test_seeded_charges() {
+        let factory = ElectrostaticFactory::<Coulomb, Newtonian>::default();
+        let charges = |seed: u64| -> Vec<Float> {
+            let sim = factory.new_seeded(8, seed);
+            sim.mass_iter().map(|m| m.charge).collect()
+        };
+        assert!(charges(3).iter().all(|charge| charge.abs() == 1.0));
+        assert_eq!(charges(3), charges(3));
+    }
+
+    #[test]
+    fn test_momentum_conserved() {
+        let mut sim = ElectrostaticSimulator {
+            masses: (0..10).map(|_| Mass::new_random_charged()).collect(),
+            tests: Vec::new(),
+            electric: Coulomb::default(),
+            gravity: Some(Newtonian::default()),
+            fields: ExternalFields::default(),
+        };
+        let momentum = |sim: &ElectrostaticSimulator<Coulomb, Newtonian>| {
+            sim.masses
+                .iter()
+                .fold(Point::ZERO, |p, m| p + m.velocity * m.mass)
+        };
+        let before = momentum(&sim);
+
+        sim.step();
+
+        assert!((momentum(&sim) - before).magnitude() < 1e-9);
+    }
+}
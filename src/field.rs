@@ -0,0 +1,152 @@
+use super::*;
+
+/// A fixed background potential whose source is not simulated, like a galactic halo.
+///
+/// Fields act per unit mass, so they give the acceleration of a body at a position.
+pub trait ExternalField: Debug + Send + Sync {
+    fn acceleration(&self, position: Point) -> Point;
+}
+
+/// The external fields a simulator adds to the forces it computes itself.
+#[derive(Debug, Default)]
+pub struct ExternalFields(Vec<Box<dyn ExternalField>>);
+
+impl ExternalFields {
+    pub fn push(&mut self, field: Box<dyn ExternalField>) {
+        self.0.push(field);
+    }
+
+    /// Total acceleration of all the fields at `position`.
+    pub fn acceleration(&self, position: Point) -> Point {
+        self.0
+            .iter()
+            .fold(Point::ZERO, |a, field| a + field.acceleration(position))
+    }
+}
+
+/// A fixed body of `mass` at `position` pulling through `law`.
+#[derive(Debug, Copy, Clone)]
+pub struct PointMass<L: ForceLaw = Newtonian> {
+    pub law: L,
+    pub position: Point,
+    pub mass: Float,
+}
+
+impl<L: ForceLaw> ExternalField for PointMass<L> {
+    fn acceleration(&self, position: Point) -> Point {
+        self.law.force(1.0, position, self.mass, self.position)
+    }
+}
+
+/// The same acceleration everywhere, like gravity near the surface of a planet.
+#[derive(Debug, Copy, Clone)]
+pub struct Uniform(pub Point);
+
+impl ExternalField for Uniform {
+    fn acceleration(&self, _position: Point) -> Point {
+        self.0
+    }
+}
+
+/// A spring pulling everything back towards `center`; bodies at rest oscillate with an angular
+/// frequency of `sqrt(k)`.
+#[derive(Debug, Copy, Clone)]
+pub struct HarmonicTrap {
+    pub center: Point,
+    pub k: Float,
+}
+
+impl ExternalField for HarmonicTrap {
+    fn acceleration(&self, position: Point) -> Point {
+        (self.center - position) * self.k
+    }
+}
+
+/// A dark matter halo with a flat rotation curve of speed `v0` outside `core_radius`.
+#[derive(Debug, Copy, Clone)]
+pub struct LogarithmicHalo {
+    pub center: Point,
+    pub v0: Float,
+    pub core_radius: Float,
+}
+
+impl ExternalField for LogarithmicHalo {
+    fn acceleration(&self, position: Point) -> Point {
+        let diff = self.center - position;
+        diff * (self.v0 * self.v0
+            / (self.core_radius * self.core_radius + diff.magnitude_squared()))
+    }
+}
+
+/// A field given by a closure from position to acceleration.
+pub struct CustomField<F>(pub F)
+where
+    F: Fn(Point) -> Point + Send + Sync;
+
+impl<F> Debug for CustomField<F>
+where
+    F: Fn(Point) -> Point + Send + Sync,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str("CustomField")
+    }
+}
+
+impl<F> ExternalField for CustomField<F>
+where
+    F: Fn(Point) -> Point + Send + Sync,
+{
+    fn acceleration(&self, position: Point) -> Point {
+        (self.0)(position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fields_add_up() {
+        let mut fields = ExternalFields::default();
+        assert_eq!(fields.acceleration(Point(1.0, 1.0)), Point::ZERO);
+
+        fields.push(Box::new(Uniform(Point(0.0, -1.0))));
+        fields.push(Box::new(CustomField(|p: Point| p * 2.0)));
+        assert_eq!(fields.acceleration(Point(1.0, 1.0)), Point(2.0, 1.0));
+    }
+
+    #[test]
+    fn test_point_mass() {
+        let field = PointMass {
+            law: Newtonian::default(),
+            position: Point(2.0, 0.0),
+            mass: 4.0,
+        };
+        assert_eq!(field.acceleration(Point::ZERO), Point(1.0, 0.0));
+    }
+
+    #[test]
+    fn test_harmonic_trap() {
+        let field = HarmonicTrap {
+            center: Point(1.0, 1.0),
+            k: 2.0,
+        };
+        assert_eq!(field.acceleration(Point(1.0, 1.0)), Point::ZERO);
+        assert_eq!(field.acceleration(Point(2.0, 0.0)), Point(-2.0, 2.0));
+    }
+
+    #[test]
+    fn test_logarithmic_halo_flat_rotation() {
+        let field = LogarithmicHalo {
+            center: Point::ZERO,
+            v0: 3.0,
+            core_radius: 0.01,
+        };
+        // circular speed sqrt(r * |a|) approaches v0 far outside the core
+        for r in [10.0, 100.0, 1000.0].iter() {
+            let a = field.acceleration(Point(*r, 0.0));
+            assert!(a.0 < 0.0);
+            assert!(((r * a.magnitude()).sqrt() - field.v0).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use super::*;
+use rustfft::num_complex::Complex;
+
+/// Builds a fast multipole simulator for logarithmic gravity, which in two dimensions is the
+/// real part of the complex potential `Σ q log(z - z_j)`. Boxes of a uniform quadtree summarize
+/// their bodies as multipole expansions of that potential to `order` terms, and far boxes act on
+/// each other through local expansions, so a step costs O(N) for a given accuracy. Bodies in
+/// neighboring leaves feel each other directly.
+///
+/// `MattFactory` is the special case of a single box and a zeroth order expansion.
+#[derive(Debug)]
+pub struct FmmFactory {
+    pub law: Logarithmic,
+    /// Terms kept in each expansion; the error falls roughly as `2^-order`.
+    pub order: usize,
+    /// Bodies per leaf the quadtree is sized for, on average.
+    pub leaf_size: usize,
+}
+
+impl Default for FmmFactory {
+    fn default() -> Self {
+        FmmFactory {
+            law: Logarithmic::default(),
+            order: 10,
+            leaf_size: 16,
+        }
+    }
+}
+
+impl SimFactory for FmmFactory {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        Box::new(FmmSimulator {
+            masses,
+            law: self.law,
+            fields: ExternalFields::default(),
+            order: self.order,
+            leaf_size: self.leaf_size,
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("Fast Multipole Simulator")
+    }
+}
+
+/// Coefficients of an expansion about the center of a box. A multipole expansion stands for
+/// `a_0 log(z - c) + Σ a_k (z - c)^-k` and a local one for `Σ b_l (z - c)^l`.
+type Expansion = Vec<Complex<Float>>;
+
+fn complex(p: Point) -> Complex<Float> {
+    Complex::new(p.0, p.1)
+}
+
+/// Pascal's triangle up to row `n`.
+fn binomials(n: usize) -> Vec<Vec<Float>> {
+    let mut rows: Vec<Vec<Float>> = vec![vec![1.0]];
+    for i in 1..=n {
+        let mut row = vec![1.0; i + 1];
+        for k in 1..i {
+            row[k] = rows[i - 1][k - 1] + rows[i - 1][k];
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+/// `z^0` through `z^n`.
+fn powers(z: Complex<Float>, n: usize) -> Vec<Complex<Float>> {
+    let mut powers = vec![Complex::new(1.0, 0.0); n + 1];
+    for k in 1..=n {
+        powers[k] = powers[k - 1] * z;
+    }
+    powers
+}
+
+/// Adds the multipole expansion `a`, about a center `offset` from ours, to `b`.
+fn shift_multipole(
+    a: &[Complex<Float>],
+    offset: Complex<Float>,
+    binomial: &[Vec<Float>],
+    b: &mut [Complex<Float>],
+) {
+    let p = a.len() - 1;
+    let z = powers(offset, p);
+    b[0] += a[0];
+    for l in 1..=p {
+        let mut sum = -a[0] * z[l] / l as Float;
+        for k in 1..=l {
+            sum += a[k] * z[l - k] * binomial[l - 1][k - 1];
+        }
+        b[l] += sum;
+    }
+}
+
+/// Adds the local expansion of the multipole expansion `a`, about a center `offset` from ours,
+/// to `b`. The constant term is left out, since only the gradient matters.
+fn multipole_to_local(
+    a: &[Complex<Float>],
+    offset: Complex<Float>,
+    binomial: &[Vec<Float>],
+    b: &mut [Complex<Float>],
+) {
+    let p = a.len() - 1;
+    let inverse = powers(offset.inv(), 2 * p);
+    for l in 1..=p {
+        let mut sum = -a[0] / l as Float;
+        for k in 1..=p {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            sum += a[k] * inverse[k] * (sign * binomial[l + k - 1][k - 1]);
+        }
+        b[l] += sum * inverse[l];
+    }
+}
+
+/// Adds the local expansion `b`, about a center `offset` from ours, to `c`.
+fn shift_local(
+    b: &[Complex<Float>],
+    offset: Complex<Float>,
+    binomial: &[Vec<Float>],
+    c: &mut [Complex<Float>],
+) {
+    let p = b.len() - 1;
+    let z = powers(offset, p);
+    for m in 1..=p {
+        for l in m..=p {
+            c[m] += b[l] * z[l - m] * binomial[l][m];
+        }
+    }
+}
+
+/// A square divided `levels` times into quarters, with `2^level` boxes on a side at each level.
+#[derive(Debug)]
+struct Quadtree {
+    levels: usize,
+    corner: Point,
+    size: Float,
+}
+
+impl Quadtree {
+    /// The smallest square around `masses`, divided until leaves hold about `leaf_size` each.
+    fn around(masses: &[Mass], leaf_size: usize) -> Quadtree {
+        let mut min = Point(Float::MAX, Float::MAX);
+        let mut max = Point(Float::MIN, Float::MIN);
+        for x in masses.iter() {
+            min = Point(min.0.min(x.position.0), min.1.min(x.position.1));
+            max = Point(max.0.max(x.position.0), max.1.max(x.position.1));
+        }
+        let size = (max.0 - min.0).max(max.1 - min.1);
+        let levels = ((masses.len() as Float / leaf_size.max(1) as Float).ln()
+            / (4.0 as Float).ln())
+        .ceil()
+        .clamp(2.0, 10.0) as usize;
+        Quadtree {
+            levels,
+            corner: min,
+            // a little slack keeps the far edge inside the last box
+            size: if size > 0.0 { size * (1.0 + 1e-9) } else { 1.0 },
+        }
+    }
+
+    fn side(&self, level: usize) -> usize {
+        1 << level
+    }
+
+    fn index(&self, level: usize, (x, y): (usize, usize)) -> usize {
+        y * self.side(level) + x
+    }
+
+    fn center(&self, level: usize, (x, y): (usize, usize)) -> Complex<Float> {
+        let width = self.size / self.side(level) as Float;
+        complex(self.corner + Point(x as Float + 0.5, y as Float + 0.5) * width)
+    }
+
+    fn leaf(&self, position: Point) -> (usize, usize) {
+        let side = self.side(self.levels);
+        let width = self.size / side as Float;
+        let coordinate = |x: Float| ((x / width) as usize).min(side - 1);
+        let offset = position - self.corner;
+        (coordinate(offset.0), coordinate(offset.1))
+    }
+
+    /// The box and those touching it.
+    fn neighbors(&self, level: usize, (x, y): (usize, usize)) -> Vec<(usize, usize)> {
+        let side = self.side(level);
+        let mut neighbors = Vec::with_capacity(9);
+        for j in y.saturating_sub(1)..(y + 2).min(side) {
+            for i in x.saturating_sub(1)..(x + 2).min(side) {
+                neighbors.push((i, j));
+            }
+        }
+        neighbors
+    }
+
+    fn children(&self, (x, y): (usize, usize)) -> [(usize, usize); 4] {
+        [
+            (2 * x, 2 * y),
+            (2 * x + 1, 2 * y),
+            (2 * x, 2 * y + 1),
+            (2 * x + 1, 2 * y + 1),
+        ]
+    }
+
+    /// Boxes far enough away to act through their expansions, but not already covered by the
+    /// parent's: children of the parent's neighbors that don't touch this box.
+    fn interaction_list(&self, level: usize, (x, y): (usize, usize)) -> Vec<(usize, usize)> {
+        self.neighbors(level - 1, (x / 2, y / 2))
+            .into_iter()
+            .flat_map(|parent| self.children(parent).to_vec())
+            .filter(|(i, j)| (*i as i64 - x as i64).abs() > 1 || (*j as i64 - y as i64).abs() > 1)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct FmmSimulator {
+    masses: Vec<Mass>,
+    law: Logarithmic,
+    fields: ExternalFields,
+    order: usize,
+    leaf_size: usize,
+}
+
+impl FmmSimulator {
+    /// Force per unit coupling on every body.
+    fn field(&self) -> Vec<Point> {
+        let p = self.order;
+        let tree = Quadtree::around(&self.masses, self.leaf_size);
+        let levels = tree.levels;
+        let binomial = binomials(2 * p);
+        let empty = |level: usize| vec![vec![Complex::new(0.0, 0.0); p + 1]; 1 << (2 * level)];
+
+        // sources by leaf
+        let mut contents: Vec<Vec<usize>> = vec![Vec::new(); 1 << (2 * levels)];
+        for (i, x) in self.masses.iter().enumerate() {
+            if !x.is_test_particle() {
+                contents[tree.index(levels, tree.leaf(x.position))].push(i);
+            }
+        }
+
+        // expand each leaf's sources about its center, then merge upward into the parents
+        let mut multipoles: Vec<Vec<Expansion>> = (0..=levels).map(empty).collect();
+        for y in 0..tree.side(levels) {
+            for x in 0..tree.side(levels) {
+                let leaf = tree.index(levels, (x, y));
+                let center = tree.center(levels, (x, y));
+                let a = &mut multipoles[levels][leaf];
+                for j in contents[leaf].iter() {
+                    let q = self.law.coupling(&self.masses[*j]);
+                    let z = powers(complex(self.masses[*j].position) - center, p);
+                    a[0] += q;
+                    for k in 1..=p {
+                        a[k] -= z[k] * (q / k as Float);
+                    }
+                }
+            }
+        }
+        for level in (0..levels).rev() {
+            for y in 0..tree.side(level) {
+                for x in 0..tree.side(level) {
+                    let center = tree.center(level, (x, y));
+                    let mut a = vec![Complex::new(0.0, 0.0); p + 1];
+                    for child in tree.children((x, y)).iter() {
+                        let offset = tree.center(level + 1, *child) - center;
+                        let child = &multipoles[level + 1][tree.index(level + 1, *child)];
+                        shift_multipole(child, offset, &binomial, &mut a);
+                    }
+                    multipoles[level][tree.index(level, (x, y))] = a;
+                }
+            }
+        }
+
+        // gather the far field into local expansions, handing them down to the children
+        let mut locals: Vec<Vec<Expansion>> = (0..=levels).map(empty).collect();
+        for level in 2..=levels {
+            for y in 0..tree.side(level) {
+                for x in 0..tree.side(level) {
+                    let index = tree.index(level, (x, y));
+                    let center = tree.center(level, (x, y));
+                    let mut b = locals[level][index].clone();
+                    for source in tree.interaction_list(level, (x, y)) {
+                        let a = &multipoles[level][tree.index(level, source)];
+                        multipole_to_local(
+                            a,
+                            tree.center(level, source) - center,
+                            &binomial,
+                            &mut b,
+                        );
+                    }
+                    if level < levels {
+                        for child in tree.children((x, y)).iter() {
+                            let offset = tree.center(level + 1, *child) - center;
+                            let c = &mut locals[level + 1][tree.index(level + 1, *child)];
+                            shift_local(&b, offset, &binomial, c);
+                        }
+                    }
+                    locals[level][index] = b;
+                }
+            }
+        }
+
+        self.masses
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let leaf = tree.leaf(x.position);
+                let b = &locals[levels][tree.index(levels, leaf)];
+                let z = powers(complex(x.position) - tree.center(levels, leaf), p);
+                let derivative = (1..=p).fold(Complex::new(0.0, 0.0), |d, l| {
+                    d + b[l] * z[l - 1] * l as Float
+                });
+                // the force is -g times the conjugate of the potential's derivative
+                let mut force = Point(-derivative.re, derivative.im) * self.law.g;
+
+                for neighbor in tree.neighbors(levels, leaf) {
+                    for j in contents[tree.index(levels, neighbor)].iter() {
+                        if *j != i {
+                            let source = &self.masses[*j];
+                            force += self.law.force(
+                                1.0,
+                                x.position,
+                                self.law.coupling(source),
+                                source.position,
+                            );
+                        }
+                    }
+                }
+                force
+            })
+            .collect()
+    }
+}
+
+impl Simulator for FmmSimulator {
+    fn step(&mut self) {
+        let field = self.field();
+        let law = &self.law;
+        for (x, f) in self.masses.iter_mut().zip(field) {
+            // test particles feel the field as a unit mass would
+            let acceleration = if x.is_test_particle() {
+                f
+            } else {
+                f * (law.coupling(x) / x.mass)
+            };
+            x.velocity += acceleration + self.fields.acceleration(x.position);
+            x.position += x.velocity;
+        }
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter())
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut())
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        acceleration_of(&self.law, &self.fields, &self.masses, &[], id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.masses.push(mass);
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn random_masses(count: usize) -> Vec<Mass> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..count)
+            .map(|_| Mass {
+                position: Point(rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0)),
+                velocity: Point::ZERO,
+                mass: rng.gen_range(Mass::MIN_RANDOM_MASS..1.0),
+                charge: 0.0,
+                id: 0,
+            })
+            .collect()
+    }
+
+    /// Size of the difference from direct summation, relative to the size of the direct field.
+    fn error(order: usize, masses: &[Mass]) -> Float {
+        let sim = FmmSimulator {
+            masses: masses.to_vec(),
+            law: Logarithmic::default(),
+            fields: ExternalFields::default(),
+            order,
+            leaf_size: 8,
+        };
+        let direct = accelerations(&sim.law, &sim.fields, masses);
+        let mut difference = 0.0;
+        let mut total = 0.0;
+        // the coupling is the mass, so the acceleration is the force per unit coupling
+        for (f, a) in sim.field().into_iter().zip(direct) {
+            difference += (f - a).magnitude_squared();
+            total += a.magnitude_squared();
+        }
+        (difference / total).sqrt()
+    }
+
+    #[test]
+    fn test_binomials() {
+        let rows = binomials(4);
+        assert_eq!(rows[4], vec![1.0, 4.0, 6.0, 4.0, 1.0]);
+    }
+
+    #[test]
+    fn test_interaction_list() {
+        let tree = Quadtree {
+            levels: 3,
+            corner: Point::ZERO,
+            size: 1.0,
+        };
+        // in the middle of the box there are 27, fewer at the corners
+        assert_eq!(tree.interaction_list(3, (3, 3)).len(), 27);
+        assert_eq!(tree.interaction_list(3, (0, 0)).len(), 12);
+        assert!(!tree.interaction_list(3, (3, 3)).contains(&(4, 4)));
+    }
+
+    #[test]
+    fn test_matches_direct_summation() {
+        let masses = random_masses(500);
+        let coarse = error(4, &masses);
+        let fine = error(16, &masses);
+        assert!(fine < 1e-5, "{}", fine);
+        assert!(fine * 100.0 < coarse, "{} vs {}", fine, coarse);
+    }
+
+    #[test]
+    fn test_test_particles() {
+        let mut masses = random_masses(200);
+        masses.push(Mass::new_test_particle(Point(10.0, 10.0), Point::ZERO));
+        masses.push(Mass::new_test_particle(Point(-200.0, 50.0), Point::ZERO));
+        assert!(error(16, &masses) < 1e-5);
+    }
+}
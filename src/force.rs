@@ -0,0 +1,508 @@
+use super::*;
+
+/// A central force acting between pairs of bodies.
+///
+/// Laws are written in terms of a scalar coupling per body (mass for gravity, charge for
+/// electrostatics) so that engines which lump bodies together, like the tree in `joe.rs` or the
+/// cloud in `matt.rs`, can simply add couplings up.
+pub trait ForceLaw: Debug + Send + Sync {
+    /// The coupling of a body under this law.
+    fn coupling(&self, mass: &Mass) -> Float {
+        mass.mass
+    }
+
+    /// Signed magnitude of the force between couplings `a` and `b` separated by `r`.
+    /// Positive values pull the two together, negative values push them apart.
+    fn attraction(&self, a: Float, b: Float, r: Float) -> Float;
+
+    /// Potential energy of a pair with couplings `a` and `b` separated by `r`.
+    fn potential(&self, a: Float, b: Float, r: Float) -> Float;
+
+    /// Rate of change of `attraction` with `r`; found numerically unless a law knows better.
+    fn attraction_slope(&self, a: Float, b: Float, r: Float) -> Float {
+        let h = r * 1e-6;
+        (self.attraction(a, b, r + h) - self.attraction(a, b, r - h)) / (2.0 * h)
+    }
+
+    /// Force felt by coupling `a` at `position` due to coupling `b` at `source`.
+    fn force(&self, a: Float, position: Point, b: Float, source: Point) -> Point {
+        let diff = source - position;
+        let r = diff.magnitude();
+        if r == 0.0 {
+            // coincident bodies have no direction to pull in
+            return Point::ZERO;
+        }
+        diff * (self.attraction(a, b, r) / r)
+    }
+
+    /// Rate of change of `force` while the bodies move at `velocity` and `source_velocity`.
+    fn force_rate(
+        &self,
+        a: Float,
+        (position, velocity): (Point, Point),
+        b: Float,
+        (source, source_velocity): (Point, Point),
+    ) -> Point {
+        let diff = source - position;
+        let r = diff.magnitude();
+        if r == 0.0 {
+            return Point::ZERO;
+        }
+        // the force is diff * attraction / r, so differentiate both factors
+        let per_distance = self.attraction(a, b, r) / r;
+        let per_distance_slope = (self.attraction_slope(a, b, r) - per_distance) / r;
+        let relative = source_velocity - velocity;
+        relative * per_distance + diff * (per_distance_slope * diff.dot(relative) / r)
+    }
+
+    /// The constant `s` when this law is gravity in the plane, whose potential solves Poisson's
+    /// equation ∇²φ = sρ, so mesh solvers can work in k-space. `None` for every other law.
+    fn poisson_source(&self) -> Option<Float> {
+        None
+    }
+
+    /// Acceleration of a massless test particle at `position` due to `source`.
+    fn test_acceleration(&self, position: Point, source: &Mass) -> Point {
+        self.force(1.0, position, self.coupling(source), source.position)
+    }
+
+    /// Force felt by `mass` due to `source`.
+    fn force_between(&self, mass: &Mass, source: &Mass) -> Point {
+        self.force(
+            self.coupling(mass),
+            mass.position,
+            self.coupling(source),
+            source.position,
+        )
+    }
+}
+
+/// Inverse-square gravity, as between point masses in three dimensions.
+///
+/// A nonzero `softening` length smooths the force at short range the way a Plummer sphere
+/// would, taming close encounters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Newtonian {
+    pub g: Float,
+    pub softening: Float,
+}
+
+impl Default for Newtonian {
+    fn default() -> Self {
+        Newtonian {
+            g: 1.0,
+            softening: 0.0,
+        }
+    }
+}
+
+impl ForceLaw for Newtonian {
+    fn attraction(&self, a: Float, b: Float, r: Float) -> Float {
+        let s = r * r + self.softening * self.softening;
+        self.g * a * b * r / (s * s.sqrt())
+    }
+
+    fn potential(&self, a: Float, b: Float, r: Float) -> Float {
+        -self.g * a * b / (r * r + self.softening * self.softening).sqrt()
+    }
+
+    fn attraction_slope(&self, a: Float, b: Float, r: Float) -> Float {
+        let e2 = self.softening * self.softening;
+        let s = r * r + e2;
+        self.g * a * b * (e2 - 2.0 * r * r) / (s * s * s.sqrt())
+    }
+}
+
+/// Gravity in a true two dimensional universe: a logarithmic potential and a `1/r` force.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Logarithmic {
+    pub g: Float,
+}
+
+impl Default for Logarithmic {
+    fn default() -> Self {
+        Logarithmic { g: 1.0 }
+    }
+}
+
+impl ForceLaw for Logarithmic {
+    fn attraction(&self, a: Float, b: Float, r: Float) -> Float {
+        self.g * a * b / r
+    }
+
+    fn attraction_slope(&self, a: Float, b: Float, r: Float) -> Float {
+        -self.attraction(a, b, r) / r
+    }
+
+    fn potential(&self, a: Float, b: Float, r: Float) -> Float {
+        self.g * a * b * r.ln()
+    }
+
+    fn poisson_source(&self) -> Option<Float> {
+        // the Laplacian of ln r is 2π times a point source
+        Some(2.0 * std::f64::consts::PI * self.g)
+    }
+}
+
+/// Electrostatics between charged bodies: like charges repel and opposite charges attract.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Coulomb {
+    pub k: Float,
+}
+
+impl Default for Coulomb {
+    fn default() -> Self {
+        Coulomb { k: 1.0 }
+    }
+}
+
+impl ForceLaw for Coulomb {
+    fn coupling(&self, mass: &Mass) -> Float {
+        mass.charge
+    }
+
+    fn attraction(&self, a: Float, b: Float, r: Float) -> Float {
+        -self.k * a * b / (r * r)
+    }
+
+    fn attraction_slope(&self, a: Float, b: Float, r: Float) -> Float {
+        -2.0 * self.attraction(a, b, r) / r
+    }
+
+    fn potential(&self, a: Float, b: Float, r: Float) -> Float {
+        self.k * a * b / r
+    }
+}
+
+/// Coulomb's law screened beyond `screening_length`, as in a plasma.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Yukawa {
+    pub k: Float,
+    pub screening_length: Float,
+}
+
+impl Default for Yukawa {
+    fn default() -> Self {
+        Yukawa {
+            k: 1.0,
+            screening_length: 10.0,
+        }
+    }
+}
+
+impl ForceLaw for Yukawa {
+    fn coupling(&self, mass: &Mass) -> Float {
+        mass.charge
+    }
+
+    fn attraction(&self, a: Float, b: Float, r: Float) -> Float {
+        let screen = (-r / self.screening_length).exp();
+        -self.k * a * b * screen * (1.0 / (r * r) + 1.0 / (self.screening_length * r))
+    }
+
+    fn potential(&self, a: Float, b: Float, r: Float) -> Float {
+        self.k * a * b * (-r / self.screening_length).exp() / r
+    }
+}
+
+/// The Lennard-Jones 12-6 interaction between neutral atoms: repulsive inside `sigma`, weakly
+/// attractive outside with a well of depth `epsilon`.
+///
+/// Every body counts as a single atom, so a lumped coupling is the number of atoms it holds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LennardJones {
+    pub epsilon: Float,
+    pub sigma: Float,
+}
+
+impl Default for LennardJones {
+    fn default() -> Self {
+        LennardJones {
+            epsilon: 1.0,
+            sigma: 1.0,
+        }
+    }
+}
+
+impl ForceLaw for LennardJones {
+    fn coupling(&self, _mass: &Mass) -> Float {
+        1.0
+    }
+
+    fn attraction(&self, a: Float, b: Float, r: Float) -> Float {
+        let s6 = (self.sigma / r).powi(6);
+        -24.0 * self.epsilon * a * b * (2.0 * s6 * s6 - s6) / r
+    }
+
+    fn potential(&self, a: Float, b: Float, r: Float) -> Float {
+        let s6 = (self.sigma / r).powi(6);
+        4.0 * self.epsilon * a * b * (s6 * s6 - s6)
+    }
+}
+
+/// Accelerations of `masses` under `law` and `fields`, summing every pair directly.
+///
+/// Massless test particles are pulled like the rest but never pull back.
+pub fn accelerations<L: ForceLaw>(law: &L, fields: &ExternalFields, masses: &[Mass]) -> Vec<Point> {
+    let mut accelerations: Vec<Point> = masses
+        .iter()
+        .map(|x| fields.acceleration(x.position))
+        .collect();
+    for (i, x) in masses.iter().enumerate() {
+        if x.is_test_particle() {
+            continue;
+        }
+        for (j, y) in masses.iter().enumerate().skip(i + 1) {
+            if y.is_test_particle() {
+                accelerations[j] += law.test_acceleration(y.position, x);
+            } else {
+                let f = law.force_between(y, x);
+                accelerations[i] -= f / x.mass;
+                accelerations[j] += f / y.mass;
+            }
+        }
+        for (j, y) in masses.iter().enumerate().take(i) {
+            if y.is_test_particle() {
+                accelerations[j] += law.test_acceleration(y.position, x);
+            }
+        }
+    }
+    accelerations
+}
+
+/// Acceleration of `masses[i]` alone under `law` and `fields`, summing over every other body.
+pub fn acceleration<L: ForceLaw>(
+    law: &L,
+    fields: &ExternalFields,
+    masses: &[Mass],
+    i: usize,
+) -> Point {
+    let x = &masses[i];
+    let mut acceleration = fields.acceleration(x.position);
+    for (j, y) in masses.iter().enumerate() {
+        if j == i || y.is_test_particle() {
+            continue;
+        }
+        if x.is_test_particle() {
+            acceleration += law.test_acceleration(x.position, y);
+        } else {
+            acceleration += law.force_between(x, y) / x.mass;
+        }
+    }
+    acceleration
+}
+
+/// Acceleration of the body `id` among `masses` or the massless `tests` under `law` and `fields`,
+/// summing over every body directly; `None` when there is no such body.
+pub fn acceleration_of<L: ForceLaw>(
+    law: &L,
+    fields: &ExternalFields,
+    masses: &[Mass],
+    tests: &[Mass],
+    id: u64,
+) -> Option<Point> {
+    if let Some(i) = masses.iter().position(|m| m.id == id) {
+        return Some(acceleration(law, fields, masses, i));
+    }
+    let t = tests.iter().find(|t| t.id == id)?;
+    let sources = masses.iter().filter(|m| !m.is_test_particle());
+    Some(sources.fold(fields.acceleration(t.position), |a, m| {
+        a + law.test_acceleration(t.position, m)
+    }))
+}
+
+/// Total kinetic and potential energy of `masses` under `law`.
+pub fn energy<L: ForceLaw>(law: &L, masses: &[Mass]) -> Float {
+    let mut energy = 0.0;
+    for (i, x) in masses.iter().enumerate() {
+        energy += 0.5 * x.mass * x.velocity.magnitude_squared();
+        for y in masses.iter().skip(i + 1) {
+            let r = (x.position - y.position).magnitude();
+            energy += law.potential(law.coupling(x), law.coupling(y), r);
+        }
+    }
+    energy
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn body(position: Point, mass: Float) -> Mass {
+        Mass {
+            position,
+            velocity: Point::ZERO,
+            mass,
+            charge: 0.0,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn test_newtonian_inverse_square() {
+        let law = Newtonian::default();
+        let near = law.attraction(1.0, 1.0, 1.0);
+        let far = law.attraction(1.0, 1.0, 2.0);
+        assert!((near / far - 4.0).abs() < Point::EPSILON);
+    }
+
+    #[test]
+    fn test_logarithmic_inverse_distance() {
+        let law = Logarithmic::default();
+        let near = law.attraction(1.0, 1.0, 1.0);
+        let far = law.attraction(1.0, 1.0, 2.0);
+        assert!((near / far - 2.0).abs() < Point::EPSILON);
+    }
+
+    #[test]
+    fn test_gravity_pulls_together() {
+        let a = body(Point(-1.0, 0.0), 1.0);
+        let b = body(Point(1.0, 0.0), 2.0);
+        for law in [
+            &Newtonian::default() as &dyn ForceLaw,
+            &Logarithmic::default(),
+        ]
+        .iter()
+        {
+            let on_a = law.force_between(&a, &b);
+            let on_b = law.force_between(&b, &a);
+            assert!(on_a.0 > 0.0);
+            assert!(on_a.1 == 0.0);
+            assert_eq!(on_a, on_b.inverse());
+        }
+    }
+
+    #[test]
+    fn test_coulomb_signs() {
+        let law = Coulomb::default();
+        let here = Point(0.0, 0.0);
+        let there = Point(1.0, 0.0);
+        // like charges repel
+        assert!(law.force(1.0, here, 1.0, there).0 < 0.0);
+        assert!(law.force(-1.0, here, -1.0, there).0 < 0.0);
+        // opposite charges attract
+        assert!(law.force(1.0, here, -1.0, there).0 > 0.0);
+    }
+
+    #[test]
+    fn test_yukawa_screened() {
+        let coulomb = Coulomb::default();
+        let yukawa = Yukawa::default();
+        let r = 3.0 * yukawa.screening_length;
+        assert!(yukawa.attraction(1.0, 1.0, r) < 0.0);
+        assert!(yukawa.attraction(1.0, 1.0, r).abs() < coulomb.attraction(1.0, 1.0, r).abs());
+    }
+
+    #[test]
+    fn test_lennard_jones_well() {
+        let law = LennardJones::default();
+        let minimum = (2.0 as Float).powf(1.0 / 6.0) * law.sigma;
+        assert!(law.attraction(1.0, 1.0, 0.9 * minimum) < 0.0);
+        assert!(law.attraction(1.0, 1.0, minimum).abs() < Point::EPSILON);
+        assert!(law.attraction(1.0, 1.0, 1.1 * minimum) > 0.0);
+        assert!((law.potential(1.0, 1.0, minimum) + law.epsilon).abs() < Point::EPSILON);
+    }
+
+    #[test]
+    fn test_coincident_bodies() {
+        let law = Newtonian::default();
+        assert_eq!(law.force(1.0, Point::ZERO, 1.0, Point::ZERO), Point::ZERO);
+    }
+
+    #[test]
+    fn test_force_is_potential_gradient() {
+        let laws: Vec<Box<dyn ForceLaw>> = vec![
+            Box::new(Newtonian::default()),
+            Box::new(Newtonian {
+                g: 2.0,
+                softening: 1.0,
+            }),
+            Box::new(Logarithmic::default()),
+            Box::new(Coulomb::default()),
+            Box::new(Yukawa::default()),
+            Box::new(LennardJones::default()),
+        ];
+        let h = 1e-6;
+        for law in laws.iter() {
+            for r in [1.1, 1.5, 2.0, 5.0].iter() {
+                let slope =
+                    (law.potential(2.0, 3.0, r + h) - law.potential(2.0, 3.0, r - h)) / (2.0 * h);
+                let attraction = law.attraction(2.0, 3.0, *r);
+                assert!(
+                    (slope - attraction).abs() < 1e-5 * attraction.abs().max(1.0),
+                    "{:?} at {}: {} vs {}",
+                    law,
+                    r,
+                    slope,
+                    attraction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_force_rate() {
+        let laws: Vec<Box<dyn ForceLaw>> = vec![
+            Box::new(Newtonian::default()),
+            Box::new(Newtonian {
+                g: 2.0,
+                softening: 1.0,
+            }),
+            Box::new(Logarithmic::default()),
+            Box::new(Coulomb::default()),
+            Box::new(Yukawa::default()),
+            Box::new(LennardJones::default()),
+        ];
+        let (position, velocity) = (Point(0.3, -0.2), Point(0.5, 1.0));
+        let (source, source_velocity) = (Point(1.5, 0.7), Point(-0.25, 0.1));
+        let h = 1e-6;
+        for law in laws.iter() {
+            let force_at = |t: Float| {
+                law.force(
+                    2.0,
+                    position + velocity * t,
+                    -3.0,
+                    source + source_velocity * t,
+                )
+            };
+            let expected = (force_at(h) - force_at(-h)) / (2.0 * h);
+            let rate = law.force_rate(2.0, (position, velocity), -3.0, (source, source_velocity));
+            assert!(
+                (rate - expected).magnitude() < 1e-5 * expected.magnitude().max(1.0),
+                "{:?}: {:?} vs {:?}",
+                law,
+                rate,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_accelerations() {
+        let law = Newtonian::default();
+        let masses = vec![
+            Mass::new_test_particle(Point(0.0, 1.0), Point::ZERO),
+            body(Point::ZERO, 2.0),
+            body(Point(1.0, 0.0), 1.0),
+        ];
+        let a = accelerations(&law, &ExternalFields::default(), &masses);
+        assert_eq!(a[1], Point(1.0, 0.0));
+        assert_eq!(a[2], Point(-2.0, 0.0));
+        // the test particle feels both, pulling on neither
+        let expected = Point(0.0, -2.0) + Point(1.0, -1.0) / (2.0 as Float).powf(1.5);
+        assert!((a[0] - expected).magnitude() < Point::EPSILON);
+        for (i, ai) in a.iter().enumerate() {
+            let alone = acceleration(&law, &ExternalFields::default(), &masses, i);
+            assert!((alone - *ai).magnitude() < Point::EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_energy() {
+        let law = Newtonian::default();
+        let mut x = body(Point::ZERO, 2.0);
+        x.velocity = Point(1.0, 0.0);
+        let y = body(Point(0.0, 2.0), 1.0);
+        assert!((energy(&law, &[x, y]) - (1.0 - 1.0)).abs() < Point::EPSILON);
+    }
+}
@@ -0,0 +1,381 @@
+use super::render::*;
+use super::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Levels of red, green and blue in the fixed palette every frame is mapped to. Green gets the
+/// extra level since the eye tells its shades apart best.
+const LEVELS: [usize; 3] = [6, 7, 6];
+
+/// Index of the palette color nearest `pixel`.
+fn palette_index(pixel: [u8; 3]) -> u8 {
+    let level = |c: u8, levels: usize| (c as usize * (levels - 1) + 127) / 255;
+    let (r, g, b) = (
+        level(pixel[0], LEVELS[0]),
+        level(pixel[1], LEVELS[1]),
+        level(pixel[2], LEVELS[2]),
+    );
+    ((r * LEVELS[1] + g) * LEVELS[2] + b) as u8
+}
+
+/// The 256 colors of the global color table, the unused ones black.
+fn palette() -> Vec<u8> {
+    let mut table = Vec::with_capacity(256 * 3);
+    let shade = |i: usize, levels: usize| (i * 255 / (levels - 1)) as u8;
+    for r in 0..LEVELS[0] {
+        for g in 0..LEVELS[1] {
+            for b in 0..LEVELS[2] {
+                table.extend_from_slice(&[
+                    shade(r, LEVELS[0]),
+                    shade(g, LEVELS[1]),
+                    shade(b, LEVELS[2]),
+                ]);
+            }
+        }
+    }
+    table.resize(256 * 3, 0);
+    table
+}
+
+/// Bits of variable width packed least significant first, as GIF wants them.
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u32) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compresses 8 bit palette indices the way GIF decoders expect.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const MAX_CODE: u16 = 4095;
+
+    let mut out = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = END + 1;
+    let mut width = 9;
+    out.write(CLEAR, width);
+
+    let mut indices = indices.iter();
+    let mut prefix = match indices.next() {
+        Some(first) => *first as u16,
+        None => {
+            out.write(END, width);
+            return out.finish();
+        }
+    };
+    for index in indices {
+        if let Some(code) = table.get(&(prefix, *index)) {
+            prefix = *code;
+            continue;
+        }
+        out.write(prefix, width);
+        if next >= MAX_CODE {
+            // the table is full, so start a new one
+            out.write(CLEAR, width);
+            table.clear();
+            next = END + 1;
+            width = 9;
+        } else {
+            table.insert((prefix, *index), next);
+            // decoders widen codes as soon as the code just made needs it
+            if next == 1 << width && width < 12 {
+                width += 1;
+            }
+            next += 1;
+        }
+        prefix = *index as u16;
+    }
+    out.write(prefix, width);
+    out.write(END, width);
+    out.finish()
+}
+
+/// Writes an animated GIF that loops forever, one `Image` per frame, every frame mapped to a fixed
+/// palette of 252 colors.
+#[derive(Debug)]
+pub struct GifEncoder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    /// Time each frame is shown, in hundredths of a second.
+    delay: u16,
+    frames: usize,
+}
+
+impl<W: Write> GifEncoder<W> {
+    /// Starts an animation of `width` by `height` frames shown `fps` times a second. Most viewers
+    /// won't go above 50 frames a second, and some slow anything above that right down.
+    pub fn new(mut out: W, width: usize, height: usize, fps: Float) -> io::Result<GifEncoder<W>> {
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("a GIF can't be {} by {}", width, height),
+            ));
+        }
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        // a global table of 256 colors of 8 bits each, background color 0, square pixels
+        out.write_all(&[0xf7, 0, 0])?;
+        out.write_all(&palette())?;
+        // loop forever
+        out.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        let delay = (100.0 / fps).round().max(1.0).min(u16::MAX as Float) as u16;
+        Ok(GifEncoder {
+            out,
+            width,
+            height,
+            delay,
+            frames: 0,
+        })
+    }
+
+    pub fn add_frame(&mut self, image: &Image) -> io::Result<()> {
+        if image.width != self.width || image.height != self.height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "every frame must be the size of the animation",
+            ));
+        }
+        // graphic control: each frame replaces the last after the delay, nothing transparent
+        self.out.write_all(&[0x21, 0xf9, 4, 0x04])?;
+        self.out.write_all(&self.delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0])?;
+
+        let indices: Vec<u8> = image.pixels.iter().map(|p| palette_index(*p)).collect();
+        self.out.write_all(&[8])?;
+        for block in lzw(&indices).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames added so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Ends the animation, handing back what it was written to.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3b])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Renders a simulator into an animated GIF, a frame for every `every`th step.
+#[derive(Debug)]
+pub struct GifAnimation<W: Write> {
+    encoder: GifEncoder<W>,
+    pub renderer: Renderer,
+    /// Only every `every`th step becomes a frame, starting with the first.
+    pub every: usize,
+    observed: usize,
+}
+
+impl<W: Write> GifAnimation<W> {
+    pub fn new(out: W, renderer: Renderer, fps: Float) -> io::Result<GifAnimation<W>> {
+        Ok(GifAnimation {
+            encoder: GifEncoder::new(out, renderer.width, renderer.height, fps)?,
+            renderer,
+            every: 1,
+            observed: 0,
+        })
+    }
+
+    /// Draws the initial state, then steps `sim` `steps` times drawing each new state, then ends
+    /// the animation.
+    pub fn run(mut self, sim: &mut dyn Simulator, steps: usize) -> io::Result<W> {
+        self.observe(sim)?;
+        for _i in 0..steps {
+            sim.step();
+            self.observe(sim)?;
+        }
+        self.finish()
+    }
+
+    /// Adds `sim` as it is now as a frame if this step is one to keep.
+    pub fn observe(&mut self, sim: &dyn Simulator) -> io::Result<()> {
+        let step = self.observed;
+        self.observed += 1;
+        if step % self.every.max(1) != 0 {
+            return Ok(());
+        }
+        let image = self.renderer.render(sim);
+        self.encoder.add_frame(&image)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.encoder.frames()
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+
+    /// Decodes a GIF LZW stream of 8 bit indices.
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..=255u8).map(|i| vec![i]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+        };
+        reset(&mut table);
+        let (mut width, mut bit, mut out) = (9, 0, Vec::new());
+        let mut previous: Option<Vec<u8>> = None;
+        loop {
+            let mut code = 0usize;
+            for i in 0..width {
+                let b = bit + i;
+                code |= ((data[b / 8] >> (b % 8)) as usize & 1) << i;
+            }
+            bit += width;
+            if code == 256 {
+                reset(&mut table);
+                width = 9;
+                previous = None;
+                continue;
+            }
+            if code == 257 {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                (None, None) => panic!("bad code {}", code),
+            };
+            if let Some(mut previous) = previous {
+                previous.push(entry[0]);
+                table.push(previous);
+                if table.len() == 1 << width && width < 12 {
+                    width += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        let table = palette();
+        for pixel in [[0, 0, 0], [255, 255, 255], [255, 0, 0], [51, 85, 204]].iter() {
+            let i = palette_index(*pixel) as usize;
+            assert_eq!(&table[i * 3..i * 3 + 3], pixel);
+        }
+        assert_eq!(palette_index([255, 255, 255]), 251);
+        // in between colors go to the nearest level
+        assert_eq!(palette_index([30, 0, 0]), palette_index([51, 0, 0]));
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let short: Vec<u8> = vec![1, 1, 1, 1, 2, 1, 1, 1, 1, 2];
+        assert_eq!(unlzw(&lzw(&short)), short);
+        assert_eq!(unlzw(&lzw(&[])), Vec::<u8>::new());
+
+        // enough variety to fill the code table and start over
+        let long: Vec<u8> = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        assert_eq!(unlzw(&lzw(&long)), long);
+
+        let flat = vec![9u8; 50_000];
+        let packed = lzw(&flat);
+        assert!(packed.len() < 1000);
+        assert_eq!(unlzw(&packed), flat);
+    }
+
+    #[test]
+    fn test_animation() {
+        let mut sim = NoGravityFactory {}.with_masses(vec![
+            Mass {
+                position: Point(-5.0, 0.0),
+                velocity: Point(1.0, 0.0),
+                mass: 1.0,
+                charge: 0.0,
+                id: 1,
+            },
+            Mass::new_test_particle(Point(5.0, 5.0), Point::ZERO),
+        ]);
+        let mut animation = GifAnimation::new(Vec::new(), Renderer::new(40, 30), 25.0).unwrap();
+        animation.every = 2;
+        for step in 0..5 {
+            if step > 0 {
+                sim.step();
+            }
+            animation.observe(&*sim).unwrap();
+        }
+        assert_eq!(animation.frames(), 3);
+        let gif = animation.finish().unwrap();
+
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(&gif[6..10], &[40, 0, 30, 0]);
+        assert_eq!(gif.last(), Some(&0x3b));
+
+        // the first frame follows the header, color table and loop extension
+        let frame = 13 + 768 + 19;
+        assert_eq!(&gif[frame..frame + 4], &[0x21, 0xf9, 4, 0x04]);
+        assert_eq!(&gif[frame + 4..frame + 6], &[4, 0]);
+        assert_eq!(gif[frame + 8], 0x2c);
+
+        let mut data = Vec::new();
+        let mut at = frame + 8 + 10 + 1;
+        while gif[at] != 0 {
+            let n = gif[at] as usize;
+            data.extend_from_slice(&gif[at + 1..at + 1 + n]);
+            at += n + 1;
+        }
+        let indices = unlzw(&data);
+        assert_eq!(indices.len(), 40 * 30);
+        assert!(indices.iter().any(|i| *i != 0));
+    }
+
+    #[test]
+    fn test_frame_size_checked() {
+        let mut encoder = GifEncoder::new(Vec::new(), 4, 4, 10.0).unwrap();
+        assert!(encoder.add_frame(&Image::new(5, 4, [0, 0, 0])).is_err());
+        assert!(GifEncoder::new(Vec::new(), 70_000, 4, 10.0).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use super::*;
+
+/// Builds a direct summation simulator using the fourth order Hermite predictor-corrector, which
+/// needs the jerk, the rate of change of each acceleration, alongside the acceleration itself.
+#[derive(Debug)]
+pub struct HermiteFactory<L: ForceLaw = Newtonian> {
+    pub law: L,
+    pub time_step: Float,
+}
+
+impl<L: ForceLaw + Default> Default for HermiteFactory<L> {
+    fn default() -> Self {
+        HermiteFactory {
+            law: L::default(),
+            time_step: 1.0,
+        }
+    }
+}
+
+impl<L: ForceLaw + Clone + 'static> SimFactory for HermiteFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        Box::new(HermiteSimulator {
+            masses,
+            derivatives: None,
+            law: self.law.clone(),
+            fields: ExternalFields::default(),
+            time_step: self.time_step,
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("Hermite Simulator")
+    }
+}
+
+/// Acceleration and jerk of a body.
+type Derivatives = (Point, Point);
+
+#[derive(Debug)]
+struct HermiteSimulator<L: ForceLaw> {
+    masses: Vec<Mass>,
+    /// Derivatives at the current positions, kept from the corrector of the previous step.
+    derivatives: Option<Vec<Derivatives>>,
+    law: L,
+    fields: ExternalFields,
+    time_step: Float,
+}
+
+impl<L: ForceLaw> HermiteSimulator<L> {
+    fn derivatives(&self, masses: &[Mass]) -> Vec<Derivatives> {
+        let mut derivatives: Vec<Derivatives> =
+            masses.iter().map(|x| self.field_derivatives(x)).collect();
+        for (i, x) in masses.iter().enumerate() {
+            if x.is_test_particle() {
+                continue;
+            }
+            let source = (x.position, x.velocity);
+            for (j, y) in masses.iter().enumerate() {
+                if j == i {
+                    continue;
+                }
+                let target = (y.position, y.velocity);
+                if y.is_test_particle() {
+                    let coupling = self.law.coupling(x);
+                    derivatives[j].0 += self.law.force(1.0, y.position, coupling, x.position);
+                    derivatives[j].1 += self.law.force_rate(1.0, target, coupling, source);
+                } else if j > i {
+                    let (a, b) = (self.law.coupling(y), self.law.coupling(x));
+                    let f = self.law.force(a, y.position, b, x.position);
+                    let rate = self.law.force_rate(a, target, b, source);
+                    derivatives[j].0 += f / y.mass;
+                    derivatives[j].1 += rate / y.mass;
+                    derivatives[i].0 -= f / x.mass;
+                    derivatives[i].1 -= rate / x.mass;
+                }
+            }
+        }
+        derivatives
+    }
+
+    /// Acceleration of the external fields, and its rate of change along the body's path.
+    fn field_derivatives(&self, x: &Mass) -> Derivatives {
+        let acceleration = self.fields.acceleration(x.position);
+        let speed = x.velocity.magnitude();
+        if speed == 0.0 {
+            return (acceleration, Point::ZERO);
+        }
+        let h = 1e-6 * (1.0 + x.position.magnitude());
+        let along = x.velocity * (h / speed);
+        let jerk = (self.fields.acceleration(x.position + along)
+            - self.fields.acceleration(x.position - along))
+            * (speed / (2.0 * h));
+        (acceleration, jerk)
+    }
+}
+
+impl<L: ForceLaw> Simulator for HermiteSimulator<L> {
+    fn step(&mut self) {
+        let dt = self.time_step;
+        let old = match self.derivatives.take() {
+            Some(derivatives) => derivatives,
+            None => self.derivatives(&self.masses),
+        };
+
+        // predict with the Taylor series we know
+        let predicted: Vec<Mass> = self
+            .masses
+            .iter()
+            .zip(old.iter())
+            .map(|(x, (a, j))| Mass {
+                position: x.position
+                    + x.velocity * dt
+                    + *a * (dt * dt / 2.0)
+                    + *j * (dt * dt * dt / 6.0),
+                velocity: x.velocity + *a * dt + *j * (dt * dt / 2.0),
+                ..*x
+            })
+            .collect();
+
+        // correct with the derivatives at the predicted positions
+        let new = self.derivatives(&predicted);
+        for (i, x) in self.masses.iter_mut().enumerate() {
+            let (a0, j0) = old[i];
+            let (a1, j1) = new[i];
+            let velocity = x.velocity + (a0 + a1) * (dt / 2.0) + (j0 - j1) * (dt * dt / 12.0);
+            x.position += (x.velocity + velocity) * (dt / 2.0) + (a0 - a1) * (dt * dt / 12.0);
+            x.velocity = velocity;
+        }
+
+        // close enough to the derivatives at the corrected positions to start the next step
+        self.derivatives = Some(new);
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+        self.derivatives = None;
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter())
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        // whatever is changed, the cached derivatives no longer hold
+        self.derivatives = None;
+        Box::new(self.masses.iter_mut())
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        acceleration_of(&self.law, &self.fields, &self.masses, &[], id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.masses.push(mass);
+        self.derivatives = None;
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        self.derivatives = None;
+        remove_by_id(&mut self.masses, id)
+    }
+
+    fn time_step(&self) -> Float {
+        self.time_step
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::initial::*;
+    use crate::matt::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// Relative change in energy under `law` after running `factory` on `masses` for `steps`.
+    fn energy_error(
+        factory: &dyn SimFactory,
+        law: Newtonian,
+        masses: &[Mass],
+        steps: usize,
+    ) -> Float {
+        let before = energy(&law, masses);
+        let mut sim = factory.with_masses(masses.to_vec());
+        for _i in 0..steps {
+            sim.step();
+        }
+        let after: Vec<Mass> = sim.mass_iter().cloned().collect();
+        ((energy(&law, &after) - before) / before).abs()
+    }
+
+    /// The same for the first order kick and drift the simpler engines take, with every force
+    /// summed directly.
+    fn direct_energy_error(law: Newtonian, masses: &[Mass], steps: usize) -> Float {
+        let before = energy(&law, masses);
+        let mut masses = masses.to_vec();
+        for _i in 0..steps {
+            let accelerations = accelerations(&law, &ExternalFields::default(), &masses);
+            for (x, a) in masses.iter_mut().zip(accelerations) {
+                x.velocity += a;
+                x.position += x.velocity;
+            }
+        }
+        ((energy(&law, &masses) - before) / before).abs()
+    }
+
+    #[test]
+    fn test_plummer_energy() {
+        let masses = plummer(&mut StdRng::seed_from_u64(7), 32, 100.0, 10.0, 1.0);
+        let law = Newtonian {
+            g: 1.0,
+            softening: 5.0,
+        };
+        let steps = 100;
+
+        let hermite = energy_error(
+            &HermiteFactory {
+                law,
+                time_step: 1.0,
+            },
+            law,
+            &masses,
+            steps,
+        );
+        let direct = direct_energy_error(law, &masses, steps);
+        let matt = energy_error(
+            &MattFactory {
+                law,
+                ..MattFactory::default()
+            },
+            law,
+            &masses,
+            steps,
+        );
+
+        // same step length, far better conservation than the first order engines
+        assert!(hermite < 1e-5, "hermite {}", hermite);
+        assert!(
+            hermite * 100.0 < direct,
+            "hermite {} direct {}",
+            hermite,
+            direct
+        );
+        assert!(hermite * 100.0 < matt, "hermite {} matt {}", hermite, matt);
+    }
+
+    #[test]
+    fn test_circular_orbit() {
+        let law = Newtonian::default();
+        let heavy = Mass {
+            position: Point::ZERO,
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let light = Mass::new_test_particle(Point(10.0, 0.0), Point(0.0, (0.1 as Float).sqrt()));
+        let mut sim = HermiteFactory {
+            law,
+            time_step: 0.5,
+        }
+        .with_masses(vec![heavy, light]);
+
+        // a quarter of the way around
+        let period = 2.0 * std::f64::consts::PI * (1000.0 as Float).sqrt();
+        let steps = (period / 4.0 / 0.5).round() as usize;
+        for _i in 0..steps {
+            sim.step();
+        }
+
+        let x = sim.mass_iter().nth(1).unwrap();
+        assert!((x.position.magnitude() - 10.0).abs() < 1e-6);
+        assert!(x.position.0.abs() < 0.1);
+        assert!(x.position.1 > 9.9);
+    }
+}
@@ -0,0 +1,88 @@
+use super::*;
+
+/// `count` equal masses drawn from a Plummer sphere of scale `radius` and `total_mass` in
+/// equilibrium under Newtonian gravity of strength `g`, seen from above: the bodies keep the x
+/// and y components of their positions and velocities.
+///
+/// The cloud is moved so that its center of mass sits still at the origin.
+pub fn plummer<R: Rng>(
+    rng: &mut R,
+    count: usize,
+    radius: Float,
+    total_mass: Float,
+    g: Float,
+) -> Vec<Mass> {
+    let mass = total_mass / count as Float;
+    let speed_scale = (g * total_mass / radius).sqrt();
+    let mut masses: Vec<Mass> = (0..count)
+        .map(|_| {
+            // invert the cumulative mass profile, skipping the far tail
+            let fraction: Float = rng.gen_range(0.0..0.99);
+            let r = radius / (fraction.powf(-2.0 / 3.0) - 1.0).sqrt();
+
+            // sample the speed as a fraction of the escape speed by rejection
+            let q = loop {
+                let q: Float = rng.gen();
+                let g: Float = rng.gen_range(0.0..0.1);
+                if g < q * q * (1.0 - q * q).powf(3.5) {
+                    break q;
+                }
+            };
+            let escape =
+                (2.0 as Float).sqrt() * speed_scale * (1.0 + r * r / (radius * radius)).powf(-0.25);
+
+            Mass {
+                position: isotropic(rng) * r,
+                velocity: isotropic(rng) * (q * escape),
+                mass,
+                charge: 0.0,
+                id: Mass::new_id(),
+            }
+        })
+        .collect();
+
+    let mut momentum = Point::ZERO;
+    let mut center = Point::ZERO;
+    for x in masses.iter() {
+        momentum += x.velocity * x.mass;
+        center += x.position * x.mass;
+    }
+    for x in masses.iter_mut() {
+        x.position -= center / total_mass;
+        x.velocity -= momentum / total_mass;
+    }
+    masses
+}
+
+/// The x and y components of a random unit vector in three dimensions.
+fn isotropic<R: Rng>(rng: &mut R) -> Point {
+    let z: Float = rng.gen_range(-1.0..1.0);
+    let angle: Float = rng.gen_range(0.0..2.0 * std::f64::consts::PI);
+    Point(angle.cos(), angle.sin()) * (1.0 - z * z).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_plummer() {
+        let masses = plummer(&mut StdRng::seed_from_u64(1), 1000, 10.0, 5.0, 1.0);
+        assert_eq!(masses.len(), 1000);
+
+        let total: Float = masses.iter().map(|x| x.mass).sum();
+        assert!((total - 5.0).abs() < Point::EPSILON);
+
+        let momentum = masses
+            .iter()
+            .fold(Point::ZERO, |p, x| p + x.velocity * x.mass);
+        assert!(momentum.magnitude() < Point::EPSILON);
+
+        // half the mass of a Plummer sphere lies within 1.3 scale radii, a bit less in projection
+        let mut radii: Vec<Float> = masses.iter().map(|x| x.position.magnitude()).collect();
+        radii.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(radii[500] > 5.0 && radii[500] < 15.0);
+    }
+}
@@ -1,12 +1,98 @@
 use super::*;
+use std::cmp::Ordering;
 use Tree::*;
 
 #[derive(Debug)]
 struct TreeNode {
     center: Point,
     mass: Float,
+    coupling: Float,
     left: Tree,
     right: Tree,
+    /// Where the k-d builder divided the node's cell; greedy nodes have no cells.
+    split: Option<Split>,
+}
+
+impl TreeNode {
+    /// Recomputes the totals from the children, which must be up to date themselves.
+    fn refit<L: ForceLaw>(&mut self, law: &L) {
+        let (left, right) = (&self.left, &self.right);
+        self.mass = left.mass() + right.mass();
+        self.center = left
+            .center()
+            .scale(left.mass())
+            .add(right.center().scale(right.mass()))
+            .scale(1.0 / self.mass);
+        self.coupling = left.coupling(law) + right.coupling(law);
+    }
+}
+
+/// Fills the place of a subtree for the moment it is moved out.
+const HOLE: Tree = Leaf(Mass {
+    position: Point::ZERO,
+    velocity: Point::ZERO,
+    mass: 0.0,
+    charge: 0.0,
+    id: 0,
+});
+
+/// The left child holds positions up to `value` along `axis`, the right child those from it on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Split {
+    axis: usize,
+    value: Float,
+}
+
+fn coordinate(position: Point, axis: usize) -> Float {
+    if axis == 0 {
+        position.0
+    } else {
+        position.1
+    }
+}
+
+/// The cell a subtree covers, the intersection of the splits above it.
+#[derive(Debug, Copy, Clone)]
+struct Bounds {
+    min: Point,
+    max: Point,
+}
+
+impl Bounds {
+    const EVERYWHERE: Bounds = Bounds {
+        min: Point(Float::NEG_INFINITY, Float::NEG_INFINITY),
+        max: Point(Float::INFINITY, Float::INFINITY),
+    };
+
+    fn contains(&self, p: Point) -> bool {
+        self.min.0 <= p.0 && p.0 <= self.max.0 && self.min.1 <= p.1 && p.1 <= self.max.1
+    }
+
+    fn divide(&self, split: Option<Split>) -> (Bounds, Bounds) {
+        match split {
+            None => (*self, *self),
+            Some(Split { axis: 0, value }) => (
+                Bounds {
+                    max: Point(value, self.max.1),
+                    ..*self
+                },
+                Bounds {
+                    min: Point(value, self.min.1),
+                    ..*self
+                },
+            ),
+            Some(Split { value, .. }) => (
+                Bounds {
+                    max: Point(self.max.0, value),
+                    ..*self
+                },
+                Bounds {
+                    min: Point(self.min.0, value),
+                    ..*self
+                },
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -25,18 +111,148 @@ impl Tree {
     }
     */
 
-    fn new_node(left: Tree, right: Tree) -> Tree {
-        let new_mass = left.mass() + right.mass();
-        Node(Box::new(TreeNode {
-            center: left
-                .center()
-                .scale(left.mass())
-                .add(right.center().scale(right.mass()))
-                .scale(1.0 / new_mass),
-            mass: new_mass,
+    fn new_node<L: ForceLaw>(law: &L, left: Tree, right: Tree) -> Tree {
+        Tree::new_split_node(law, left, right, None)
+    }
+
+    fn new_split_node<L: ForceLaw>(law: &L, left: Tree, right: Tree, split: Option<Split>) -> Tree {
+        let mut node = TreeNode {
+            center: Point::ZERO,
+            mass: 0.0,
+            coupling: 0.0,
             left,
             right,
-        }))
+            split,
+        };
+        node.refit(law);
+        Node(Box::new(node))
+    }
+
+    /// Splits `masses` at the median along `axis`, then the halves along the other axis, and so
+    /// on down, giving a tree of depth `⌈log2 N⌉` whatever order the masses come in.
+    fn new_kd<L: ForceLaw>(law: &L, mut masses: Vec<Mass>, axis: usize) -> Tree {
+        if masses.len() == 1 {
+            return Leaf(masses[0]);
+        }
+        let half = masses.len() / 2;
+        masses.select_nth_unstable_by(half, |a, b| {
+            coordinate(a.position, axis)
+                .partial_cmp(&coordinate(b.position, axis))
+                .unwrap_or(Ordering::Equal)
+        });
+        let right = masses.split_off(half);
+        let split = Split {
+            axis,
+            value: coordinate(right[0].position, axis),
+        };
+        Tree::new_split_node(
+            law,
+            Tree::new_kd(law, masses, 1 - axis),
+            Tree::new_kd(law, right, 1 - axis),
+            Some(split),
+        )
+    }
+
+    /// Takes out the bodies that have left the cells they were filed under, pushing them onto
+    /// `strays`, and refits the totals of the nodes that stay, bottom up and in place. A node left
+    /// with a single child is replaced by it. Returns false when nothing of the tree is left.
+    fn detach_strays<L: ForceLaw>(
+        &mut self,
+        law: &L,
+        bounds: Bounds,
+        strays: &mut Vec<Mass>,
+    ) -> bool {
+        let node = match self {
+            Leaf(m) if bounds.contains(m.position) => return true,
+            Leaf(m) => {
+                strays.push(*m);
+                return false;
+            }
+            Node(node) => node,
+        };
+        let (left_bounds, right_bounds) = bounds.divide(node.split);
+        let left = node.left.detach_strays(law, left_bounds, strays);
+        let right = node.right.detach_strays(law, right_bounds, strays);
+        match (left, right) {
+            (true, true) => node.refit(law),
+            (true, false) => *self = std::mem::replace(&mut node.left, HOLE),
+            (false, true) => *self = std::mem::replace(&mut node.right, HOLE),
+            (false, false) => return false,
+        }
+        true
+    }
+
+    /// Drops the bodies `keep` turns down, recomputing the totals of what is left.
+    fn retain<L: ForceLaw, F: FnMut(&Mass) -> bool>(self, law: &L, keep: &mut F) -> Option<Tree> {
+        match self {
+            Leaf(m) => keep(&m).then_some(Leaf(m)),
+            Node(node) => {
+                let TreeNode {
+                    left, right, split, ..
+                } = *node;
+                let left = left.retain(law, keep);
+                let right = right.retain(law, keep);
+                match (left, right) {
+                    (Some(left), Some(right)) => {
+                        Some(Tree::new_split_node(law, left, right, split))
+                    }
+                    (left, right) => left.or(right),
+                }
+            }
+        }
+    }
+
+    /// Files `mass` under the cell it lies in, splitting the leaf it lands on halfway between the
+    /// two along whichever axis they are further apart, and refits the nodes on the way down in
+    /// place. Greedy nodes fall back on `add_mass`.
+    fn insert<L: ForceLaw>(&mut self, law: &L, mass: Mass) {
+        match self {
+            Leaf(other) => {
+                let other = *other;
+                let offset = mass.position - other.position;
+                let axis = if offset.0.abs() >= offset.1.abs() {
+                    0
+                } else {
+                    1
+                };
+                let value = coordinate(mass.position + other.position, axis) / 2.0;
+                let (left, right) = if coordinate(offset, axis) >= 0.0 {
+                    (other, mass)
+                } else {
+                    (mass, other)
+                };
+                *self =
+                    Tree::new_split_node(law, Leaf(left), Leaf(right), Some(Split { axis, value }));
+            }
+            Node(node) if node.split.is_some() => {
+                let Split { axis, value } = node.split.unwrap();
+                if coordinate(mass.position, axis) < value {
+                    node.left.insert(law, mass);
+                } else {
+                    node.right.insert(law, mass);
+                }
+                node.refit(law);
+            }
+            tree => *tree = std::mem::replace(tree, HOLE).add_mass(law, mass),
+        }
+    }
+
+    fn stats(&self) -> TreeStats {
+        match self {
+            Leaf(_) => TreeStats {
+                depth: 0,
+                min_depth: 0,
+                leaves: 1,
+            },
+            Node(n) => {
+                let (left, right) = (n.left.stats(), n.right.stats());
+                TreeStats {
+                    depth: 1 + left.depth.max(right.depth),
+                    min_depth: 1 + left.min_depth.min(right.min_depth),
+                    leaves: left.leaves + right.leaves,
+                }
+            }
+        }
     }
 
     fn center(&self) -> Point {
@@ -53,52 +269,64 @@ impl Tree {
         }
     }
 
-    fn add_mass(self, mass_ref: Mass) -> Self {
+    fn coupling<L: ForceLaw>(&self, law: &L) -> Float {
         match self {
-            Leaf(lm) => Tree::new_node(Leaf(lm), Leaf(mass_ref)),
+            Leaf(m) => law.coupling(m),
+            Node(n) => n.coupling,
+        }
+    }
+
+    fn add_mass<L: ForceLaw>(self, law: &L, mass_ref: Mass) -> Self {
+        match self {
+            Leaf(lm) => Tree::new_node(law, Leaf(lm), Leaf(mass_ref)),
             Node(node) => {
                 let center = mass_ref.position;
                 // ignore the effect of node.mass, because it would be same for left and right
-                let mut left_force =
-                    (node.left.mass()) / node.left.center().minus(center).magnitude_squared();
-                let mut right_force =
-                    (node.right.mass()) / node.right.center().minus(center).magnitude_squared();
-                if left_force.is_nan() {
-                    println!("left NaN");
-                    left_force = rand::thread_rng().gen::<Float>();
-                }
-                if right_force.is_nan() {
-                    println!("right NaN");
-                    right_force = rand::thread_rng().gen::<Float>();
-                }
+                let pull = |child: &Tree| {
+                    let force = child.mass() / child.center().minus(center).magnitude_squared();
+                    // a massless child right on top of the body has no pull to speak of
+                    if force.is_nan() {
+                        0.0
+                    } else {
+                        force
+                    }
+                };
+                let (left_force, right_force) = (pull(&node.left), pull(&node.right));
                 if right_force > left_force {
-                    Tree::new_node(node.left, node.right.add_mass(mass_ref))
+                    Tree::new_node(law, node.left, node.right.add_mass(law, mass_ref))
                 } else {
-                    Tree::new_node(node.left.add_mass(mass_ref), node.right)
+                    Tree::new_node(law, node.left.add_mass(law, mass_ref), node.right)
                 }
             }
         }
     }
 
-    fn update_with(&mut self, force: Point) {
+    fn update_with<L: ForceLaw>(&mut self, law: &L, fields: &ExternalFields, force: Point) {
         match self {
             Node(i) => {
-                let diff = i.left.center().minus(i.right.center());
-                let f = diff
-                    .unit_vector()
-                    .scale((i.left.mass() * i.right.mass()) / diff.magnitude_squared().sqrt());
-                i.left.update_with(force.add(f));
-                i.right.update_with(force.add(f.inverse()));
+                // force on the left cloud due to the right one, and the reverse
+                let f = law.force(
+                    i.left.coupling(law),
+                    i.left.center(),
+                    i.right.coupling(law),
+                    i.right.center(),
+                );
+                i.left.update_with(law, fields, force.add(f));
+                i.right.update_with(law, fields, force.add(f.inverse()));
             }
             Leaf(mass) => {
-                let mut point_mass = mass;
-                point_mass.velocity = point_mass.velocity.add(force.scale(1.0 / point_mass.mass));
+                let point_mass = mass;
+                point_mass.velocity = point_mass
+                    .velocity
+                    .add(force.scale(1.0 / point_mass.mass))
+                    .add(fields.acceleration(point_mass.position));
                 point_mass.position = point_mass.position.add(point_mass.velocity);
             }
         }
     }
 }
 
+#[derive(Clone)]
 struct TreeIter<'a> {
     stack: Vec<&'a Tree>,
 }
@@ -134,16 +362,115 @@ impl<'a> Iterator for TreeIter<'a> {
     }
 }
 
-#[derive(Debug)]
-pub struct JoeFactory;
+/// Walks the leaves in the same order as `TreeIter`. Node totals are left as they were; they go
+/// unused until the next step, which always refits or rebuilds the whole tree first.
+struct TreeIterMut<'a> {
+    stack: Vec<&'a mut Tree>,
+}
 
-impl SimFactory for JoeFactory {
-    fn new(&self, count: usize) -> Box<dyn Simulator> {
-        let mut tree = Leaf(Mass::new_random());
-        for _i in 1..count {
-            tree = tree.add_mass(Mass::new_random());
+impl<'a> Iterator for TreeIterMut<'a> {
+    type Item = &'a mut Mass;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.stack.pop()? {
+            Node(n) => {
+                let node: &'a mut TreeNode = n;
+                self.stack.push(&mut node.right);
+                self.stack.push(&mut node.left);
+                self.next()
+            }
+            Leaf(m) => Some(m),
+        }
+    }
+}
+
+/// The shape of one of Joe's trees.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TreeStats {
+    /// Edges from the root to the deepest leaf.
+    pub depth: usize,
+    /// Edges from the root to the shallowest leaf.
+    pub min_depth: usize,
+    pub leaves: usize,
+}
+
+impl TreeStats {
+    /// The depth of a perfectly balanced tree with as many leaves, over the actual depth: one
+    /// when balanced, falling towards zero as the tree degenerates into a list.
+    pub fn balance(&self) -> Float {
+        if self.depth == 0 {
+            return 1.0;
         }
-        Box::new(JoeSimulator { tree })
+        (self.leaves as Float).log2().ceil() / self.depth as Float
+    }
+}
+
+/// How Joe's tree is put together from the masses each step.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum TreeBuilder {
+    /// Adds the masses one at a time, each descending towards whichever side pulls harder. Cheap,
+    /// but the shape depends on the order the masses arrive in.
+    #[default]
+    Greedy,
+    /// Splits at the median along alternating axes, like a k-d tree.
+    KdMedian,
+}
+
+impl TreeBuilder {
+    fn build<L: ForceLaw>(self, law: &L, masses: Vec<Mass>) -> Option<Tree> {
+        match self {
+            TreeBuilder::Greedy => {
+                let mut iter = masses.into_iter();
+                let first = iter.next()?;
+                Some(iter.fold(Leaf(first), |tree, m| tree.add_mass(law, m)))
+            }
+            TreeBuilder::KdMedian if masses.is_empty() => None,
+            TreeBuilder::KdMedian => Some(Tree::new_kd(law, masses, 0)),
+        }
+    }
+}
+
+/// When Joe's simulator builds a fresh tree.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Rebuild {
+    /// From scratch before every step.
+    #[default]
+    EveryStep,
+    /// Keep the tree between steps, re-filing only the bodies that left their k-d cells and
+    /// refreshing the totals, unless the tree's balance has fallen under `min_balance`. Greedy
+    /// trees have no cells, so they keep their shape until the balance check fails.
+    Refit { min_balance: Float },
+}
+
+/// Builds Joe's tree simulator; 2D gravity by default.
+#[derive(Debug, Default)]
+pub struct JoeFactory<L: ForceLaw = Logarithmic> {
+    pub law: L,
+    pub builder: TreeBuilder,
+    pub rebuild: Rebuild,
+}
+
+impl<L: ForceLaw> JoeFactory<L> {
+    /// The shape of the tree this factory would build for the massive bodies among `masses`.
+    pub fn tree_stats(&self, masses: &[Mass]) -> Option<TreeStats> {
+        let massive = masses.iter().filter(|m| !m.is_test_particle()).cloned();
+        let tree = self.builder.build(&self.law, massive.collect())?;
+        Some(tree.stats())
+    }
+}
+
+impl<L: ForceLaw + Clone + 'static> SimFactory for JoeFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        let law = self.law.clone();
+        let (masses, tests): (Vec<Mass>, Vec<Mass>) =
+            masses.into_iter().partition(|m| !m.is_test_particle());
+        Box::new(JoeSimulator {
+            tree: self.builder.build(&law, masses),
+            tests,
+            law,
+            fields: ExternalFields::default(),
+            builder: self.builder,
+            rebuild: self.rebuild,
+        })
     }
 
     fn name(&self) -> String {
@@ -152,35 +479,117 @@ impl SimFactory for JoeFactory {
 }
 
 #[derive(Debug)]
-struct JoeSimulator {
-    tree: Tree,
+struct JoeSimulator<L: ForceLaw> {
+    // only the massive bodies live in the tree, and there may be none of them
+    tree: Option<Tree>,
+    tests: Vec<Mass>,
+    law: L,
+    fields: ExternalFields,
+    builder: TreeBuilder,
+    rebuild: Rebuild,
 }
 
-impl JoeSimulator {
-    fn new_tree(&self) -> Tree {
-        let mut iter = self.mass_iter();
-        let mut tree = Leaf(*iter.next().unwrap());
-        for mass in iter {
-            tree = tree.add_mass(*mass);
+impl<L: ForceLaw> JoeSimulator<L> {
+    fn massive_iter(&self) -> impl Iterator<Item = &Mass> + Clone {
+        self.tree.iter().flat_map(TreeIter::new)
+    }
+
+    fn new_tree(&self) -> Option<Tree> {
+        let masses = self.massive_iter().cloned().collect();
+        let mut tree = self.builder.build(&self.law, masses)?;
+        tree.update_with(&self.law, &self.fields, Point::ZERO);
+        Some(tree)
+    }
+
+    /// The last step's tree brought up to date with where its bodies have moved, keeping its
+    /// nodes wherever no body crossed their splits.
+    fn refit_tree(&mut self, min_balance: Float) -> Option<Tree> {
+        let mut strays = Vec::new();
+        let mut tree = self.tree.take()?;
+        let kept = tree.detach_strays(&self.law, Bounds::EVERYWHERE, &mut strays);
+        let mut tree = strays
+            .into_iter()
+            .fold(kept.then_some(tree), |tree, m| match tree {
+                Some(mut tree) => {
+                    tree.insert(&self.law, m);
+                    Some(tree)
+                }
+                None => Some(Leaf(m)),
+            })?;
+
+        if tree.stats().balance() < min_balance {
+            let masses = TreeIter::new(&tree).cloned().collect();
+            tree = self.builder.build(&self.law, masses)?;
         }
-        tree.update_with(Point::ZERO);
-        tree
+        tree.update_with(&self.law, &self.fields, Point::ZERO);
+        Some(tree)
     }
 }
 
-impl Simulator for JoeSimulator {
+impl<L: ForceLaw> Simulator for JoeSimulator<L> {
     fn step(&mut self) {
-        self.tree = self.new_tree();
+        // test particles feel every mass directly, before the tree moves them
+        let mut tests = std::mem::take(&mut self.tests);
+        step_test_particles(&self.law, &self.fields, self.massive_iter(), &mut tests);
+        self.tests = tests;
+
+        self.tree = match self.rebuild {
+            Rebuild::EveryStep => self.new_tree(),
+            Rebuild::Refit { min_balance } => self.refit_tree(min_balance),
+        };
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.massive_iter().chain(self.tests.iter()))
     }
 
-    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &Mass> + 'a> {
-        Box::new(TreeIter::new(&self.tree))
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        let tree = TreeIterMut {
+            stack: self.tree.iter_mut().collect(),
+        };
+        Box::new(tree.chain(self.tests.iter_mut()))
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        let masses: Vec<Mass> = self.massive_iter().cloned().collect();
+        acceleration_of(&self.law, &self.fields, &masses, &self.tests, id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        if mass.is_test_particle() {
+            self.tests.push(mass);
+            return;
+        }
+        match self.tree.as_mut() {
+            Some(tree) => tree.insert(&self.law, mass),
+            None => self.tree = Some(Leaf(mass)),
+        }
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        let mut removed = None;
+        let tree = self.tree.take();
+        self.tree = tree.and_then(|tree| {
+            tree.retain(&self.law, &mut |x| {
+                if x.id == id {
+                    removed = Some(*x);
+                }
+                x.id != id
+            })
+        });
+        removed.or_else(|| remove_by_id(&mut self.tests, id))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn test_update_with() {
@@ -188,10 +597,16 @@ mod test {
             position: Point::ZERO,
             velocity: Point(1.0, 1.0),
             mass: 1.0,
+            charge: 0.0,
+            id: 0,
         };
         let mut test_node = Tree::Leaf(test_mass);
 
-        test_node.update_with(Point::ZERO);
+        test_node.update_with(
+            &Logarithmic::default(),
+            &ExternalFields::default(),
+            Point::ZERO,
+        );
 
         if let Leaf(ref x) = test_node {
             assert!(x.position.minus(Point(1.0, 1.0)).magnitude_squared() < Point::EPSILON);
@@ -200,7 +615,11 @@ mod test {
             panic!("Not a Leaf() when that is the only choice!!");
         }
 
-        test_node.update_with(Point(2.0, 3.0));
+        test_node.update_with(
+            &Logarithmic::default(),
+            &ExternalFields::default(),
+            Point(2.0, 3.0),
+        );
 
         if let Leaf(ref x) = test_node {
             assert!(x.position.minus(Point(4.0, 5.0)).magnitude_squared() < Point::EPSILON);
@@ -209,4 +628,306 @@ mod test {
             panic!("Not a Leaf() when that is the only choice!!");
         }
     }
+
+    #[test]
+    fn test_gravity_attracts() {
+        let law = Logarithmic::default();
+        let test_mass1 = Mass {
+            position: Point(-1.0, 0.0),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let test_mass2 = Mass {
+            position: Point(1.0, 0.0),
+            ..test_mass1
+        };
+        let mut tree = Leaf(test_mass1).add_mass(&law, test_mass2);
+
+        tree.update_with(&law, &ExternalFields::default(), Point::ZERO);
+
+        // the two masses should be moving towards each other along the axis
+        let masses: Vec<&Mass> = TreeIter::new(&tree).collect();
+        assert!(masses[0].velocity.0 > 0.0);
+        assert!(masses[1].velocity.0 < 0.0);
+        assert!(masses[0].velocity.1 == 0.0);
+        assert!(masses[1].velocity.1 == 0.0);
+    }
+
+    #[test]
+    fn test_clusters_attract() {
+        let law = Logarithmic::default();
+        let body = |x: Float, y: Float| Mass {
+            position: Point(x, y),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let left = Leaf(body(-3.0, 1.0)).add_mass(&law, body(-3.0, -1.0));
+        let right = Leaf(body(3.0, 1.0)).add_mass(&law, body(3.0, -1.0));
+        let mut tree = Tree::new_node(&law, left, right);
+
+        tree.update_with(&law, &ExternalFields::default(), Point::ZERO);
+
+        // each cluster is pulled towards the other; the force once pointed from the right
+        // cluster to the left and pushed them apart
+        let masses: Vec<&Mass> = TreeIter::new(&tree).collect();
+        assert_eq!(masses.len(), 4);
+        for x in masses.iter() {
+            assert!(x.velocity.0 * x.position.0 < 0.0, "{:?}", x);
+        }
+        let momentum = masses
+            .iter()
+            .fold(Point::ZERO, |p, x| p + x.velocity * x.mass);
+        assert!(momentum.magnitude() < Point::EPSILON);
+    }
+
+    #[test]
+    fn test_insert_onto_test_particle() {
+        let law = Logarithmic::default();
+        let spot = Point(1.0, 1.0);
+        let body = Mass {
+            position: Point(-5.0, 0.0),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 1,
+        };
+        let landing = Mass {
+            position: spot,
+            id: 2,
+            ..body
+        };
+
+        // a test particle right where a body lands has no pull, where it once had a random one,
+        // so the body always goes to the side with the real mass
+        for _i in 0..10 {
+            let tree = Leaf(Mass::new_test_particle(spot, Point::ZERO)).add_mass(&law, body);
+            match tree.add_mass(&law, landing) {
+                Node(node) => {
+                    let right: Vec<u64> = TreeIter::new(&node.right).map(|x| x.id).collect();
+                    assert_eq!(right, vec![1, 2]);
+                }
+                Leaf(_) => panic!("two bodies make a node"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_test_particle() {
+        let factory = JoeFactory::<Logarithmic>::default();
+        let test_mass = Mass {
+            position: Point(1.0, 0.0),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let mut sim = factory.with_masses(vec![
+            Mass::new_test_particle(Point(-1.0, 0.0), Point::ZERO),
+            test_mass,
+        ]);
+
+        sim.step();
+
+        // the test particle is pulled in, but doesn't pull back
+        let masses: Vec<&Mass> = sim.mass_iter().collect();
+        assert!(masses[0].velocity == Point::ZERO);
+        assert!(masses[0].position == Point(1.0, 0.0));
+        assert!(masses[1].is_test_particle());
+        assert!(masses[1].velocity.0 > 0.0);
+        assert!(masses[1].velocity.1 == 0.0);
+    }
+
+    #[test]
+    fn test_only_test_particles() {
+        let factory = JoeFactory::<Logarithmic>::default();
+        let mut sim =
+            factory.with_masses(vec![Mass::new_test_particle(Point::ZERO, Point(1.0, 1.0))]);
+
+        sim.step();
+
+        let masses: Vec<&Mass> = sim.mass_iter().collect();
+        assert_eq!(masses.len(), 1);
+        assert!(masses[0].position == Point(1.0, 1.0));
+    }
+
+    /// Bodies spaced along a line, each with a distinct mass to tell them apart.
+    fn line(count: usize) -> Vec<Mass> {
+        (0..count)
+            .map(|i| Mass {
+                position: Point(i as Float, 0.0),
+                velocity: Point::ZERO,
+                mass: 1.0 + i as Float,
+                charge: 0.0,
+                id: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_kd_balanced() {
+        let factory = JoeFactory {
+            law: Logarithmic::default(),
+            builder: TreeBuilder::KdMedian,
+            ..JoeFactory::default()
+        };
+        let stats = factory.tree_stats(&line(100)).unwrap();
+        assert_eq!(stats.leaves, 100);
+        assert_eq!(stats.depth, 7);
+        assert_eq!(stats.min_depth, 6);
+        assert_eq!(stats.balance(), 1.0);
+
+        let greedy = JoeFactory::<Logarithmic>::default()
+            .tree_stats(&line(100))
+            .unwrap();
+        assert_eq!(greedy.leaves, 100);
+        assert!(greedy.depth > stats.depth);
+        assert!(greedy.balance() < 1.0);
+
+        assert_eq!(factory.tree_stats(&[]), None);
+    }
+
+    #[test]
+    fn test_iter_visits_each_mass_once() {
+        let law = Logarithmic::default();
+        for builder in [TreeBuilder::Greedy, TreeBuilder::KdMedian].iter() {
+            let tree = builder.build(&law, line(37)).unwrap();
+            let mut masses: Vec<Float> = TreeIter::new(&tree).map(|m| m.mass).collect();
+            masses.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let expected: Vec<Float> = line(37).iter().map(|m| m.mass).collect();
+            assert_eq!(masses, expected);
+        }
+    }
+
+    #[test]
+    fn test_kd_simulation() {
+        let mut sim = JoeFactory {
+            law: Logarithmic::default(),
+            builder: TreeBuilder::KdMedian,
+            rebuild: Rebuild::EveryStep,
+        }
+        .with_masses(line(10));
+
+        sim.step();
+
+        // the ends of the line are pulled in towards the middle
+        assert_eq!(sim.mass_iter().count(), 10);
+        for x in sim.mass_iter() {
+            if x.mass == 1.0 {
+                assert!(x.velocity.0 > 0.0);
+            } else if x.mass == 10.0 {
+                assert!(x.velocity.0 < 0.0);
+            }
+        }
+    }
+
+    fn move_mass(tree: &mut Tree, mass: Float, to: Point) {
+        match tree {
+            Leaf(m) if m.mass == mass => m.position = to,
+            Leaf(_) => {}
+            Node(n) => {
+                move_mass(&mut n.left, mass, to);
+                move_mass(&mut n.right, mass, to);
+            }
+        }
+    }
+
+    #[test]
+    fn test_refit() {
+        let law = Logarithmic::default();
+        let mut tree = TreeBuilder::KdMedian.build(&law, line(8)).unwrap();
+        move_mass(&mut tree, 1.0, Point(6.5, 0.0));
+        move_mass(&mut tree, 5.0, Point(4.2, 0.0));
+
+        // only the body that crossed a split has to move
+        let mut strays = Vec::new();
+        assert!(tree.detach_strays(&law, Bounds::EVERYWHERE, &mut strays));
+        assert_eq!(strays.len(), 1);
+        assert_eq!(strays[0].mass, 1.0);
+        assert_eq!(tree.stats().leaves, 7);
+
+        tree.insert(&law, strays[0]);
+        assert_eq!(tree.stats().leaves, 8);
+        assert_eq!(tree.stats().depth, 4);
+
+        // the totals follow the bodies to where they are now
+        let masses: Vec<&Mass> = TreeIter::new(&tree).collect();
+        let total: Float = masses.iter().map(|m| m.mass).sum();
+        let center = masses
+            .iter()
+            .fold(Point::ZERO, |c, m| c + m.position * m.mass)
+            / total;
+        assert_eq!(tree.mass(), total);
+        assert!((tree.center() - center).magnitude() < Point::EPSILON);
+
+        // and a second pass finds nothing out of place
+        let mut strays = Vec::new();
+        tree.detach_strays(&law, Bounds::EVERYWHERE, &mut strays);
+        assert!(strays.is_empty());
+    }
+
+    #[test]
+    fn test_refit_in_place() {
+        let law = Logarithmic::default();
+        let mut tree = TreeBuilder::KdMedian.build(&law, line(8)).unwrap();
+        let root = |tree: &Tree| match tree {
+            Node(n) => &**n as *const TreeNode,
+            Leaf(_) => panic!("a leaf at the root"),
+        };
+        let before = root(&tree);
+
+        // nothing crosses a split, so every node stays where it was with new totals
+        move_mass(&mut tree, 8.0, Point(7.4, 0.0));
+        let mut strays = Vec::new();
+        assert!(tree.detach_strays(&law, Bounds::EVERYWHERE, &mut strays));
+        assert!(strays.is_empty());
+        assert_eq!(root(&tree), before);
+        let rebuilt = TreeBuilder::KdMedian
+            .build(&law, TreeIter::new(&tree).cloned().collect())
+            .unwrap();
+        assert!((tree.center() - rebuilt.center()).magnitude() < Point::EPSILON);
+        assert_eq!(tree.mass(), rebuilt.mass());
+
+        // a tree whose bodies all left has nothing to keep
+        let mut strays = Vec::new();
+        let bounds = Bounds {
+            min: Point(-10.0, 5.0),
+            max: Point(10.0, 6.0),
+        };
+        let mut gone = TreeBuilder::KdMedian.build(&law, line(2)).unwrap();
+        assert!(!gone.detach_strays(&law, bounds, &mut strays));
+        assert_eq!(strays.len(), 2);
+    }
+
+    #[test]
+    fn test_refit_simulation() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let masses: Vec<Mass> = (0..64)
+            .map(|i| Mass {
+                position: Point((i % 8) as Float, (i / 8) as Float) * 10.0,
+                velocity: Point::new_random_from(&mut rng),
+                mass: 1.0,
+                charge: 0.0,
+                id: 0,
+            })
+            .collect();
+        let mut sim = JoeSimulator {
+            tree: TreeBuilder::KdMedian.build(&Logarithmic::default(), masses),
+            tests: Vec::new(),
+            law: Logarithmic::default(),
+            fields: ExternalFields::default(),
+            builder: TreeBuilder::KdMedian,
+            rebuild: Rebuild::Refit { min_balance: 0.75 },
+        };
+
+        for _i in 0..20 {
+            sim.step();
+            let stats = sim.tree.as_ref().unwrap().stats();
+            assert_eq!(stats.leaves, 64);
+            assert!(stats.balance() >= 0.75);
+        }
+    }
 }
@@ -10,38 +10,353 @@ To build the tree, we need the center of mass of each cloud.
 4. repeat.
 
 */
+pub mod adaptive;
+pub mod block;
+pub mod control;
+pub mod electrostatic;
+pub mod field;
+pub mod fmm;
+pub mod force;
+pub mod gif;
+pub mod hermite;
+pub mod initial;
 pub mod joe;
 pub mod matt;
 pub mod no_gravity;
+pub mod p3m;
+pub mod pm;
 pub mod point;
+pub mod recorder;
+pub mod render;
+pub mod svg;
+pub mod terminal;
+pub mod view;
+pub mod vtk;
+use field::*;
+use force::*;
 use point::*;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::fmt::*;
 use std::iter::*;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Mass {
     pub position: Point,
     pub velocity: Point,
     pub mass: Float,
+    /// Electric charge; zero for neutral bodies.
+    pub charge: Float,
+    /// Names the body for as long as it is simulated, whatever engines do to their storage.
+    /// `Mass::new_id` hands out ids no other body has.
+    pub id: u64,
 }
 
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
 impl Mass {
+    /// Smallest mass `new_random` hands out, keeping accelerations finite.
+    pub const MIN_RANDOM_MASS: Float = 0.01;
+
+    /// An id not yet given to any other body.
+    pub fn new_id() -> u64 {
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub fn new_random() -> Mass {
+        Mass::new_random_from(&mut rand::thread_rng())
+    }
+
+    /// A random mass as `new_random` makes them, drawn from `rng` so the same seed gives the same
+    /// bodies.
+    pub fn new_random_from<R: Rng>(rng: &mut R) -> Mass {
+        Mass {
+            position: Point::new_random_from(rng) * 100.0,
+            velocity: Point::new_random_from(rng),
+            mass: rng.gen_range(Mass::MIN_RANDOM_MASS..1.0),
+            charge: 0.0,
+            id: Mass::new_id(),
+        }
+    }
+
+    /// A massless tracer that feels gravity but exerts none.
+    pub fn new_test_particle(position: Point, velocity: Point) -> Mass {
         Mass {
-            position: Point::new_random() * 100.0,
-            velocity: Point::new_random(),
-            mass: rand::thread_rng().gen::<Float>() / 1.0,
+            position,
+            velocity,
+            mass: 0.0,
+            charge: 0.0,
+            id: Mass::new_id(),
         }
     }
+
+    pub fn is_test_particle(&self) -> bool {
+        self.mass == 0.0
+    }
+
+    /// A random mass carrying a unit charge of random sign.
+    pub fn new_random_charged() -> Mass {
+        Mass::new_random_charged_from(&mut rand::thread_rng())
+    }
+
+    /// A random charged mass as `new_random_charged` makes them, drawn from `rng`.
+    pub fn new_random_charged_from<R: Rng>(rng: &mut R) -> Mass {
+        let mass = Mass::new_random_from(rng);
+        let charge = if rng.gen::<bool>() { 1.0 } else { -1.0 };
+        Mass { charge, ..mass }
+    }
 }
 
 pub trait SimFactory {
-    fn new(&self, count: usize) -> Box<dyn Simulator>;
+    #[allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
+    fn new(&self, count: usize) -> Box<dyn Simulator> {
+        self.with_masses((0..count).map(|_| Mass::new_random()).collect())
+    }
+    /// Like `new`, but the bodies are the same every time for the same `seed`.
+    fn new_seeded(&self, count: usize, seed: u64) -> Box<dyn Simulator> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.with_masses(
+            (0..count)
+                .map(|_| Mass::new_random_from(&mut rng))
+                .collect(),
+        )
+    }
+    /// Builds a simulator of the given bodies; massless ones become test particles.
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator>;
     fn name(&self) -> String;
 }
 
+/// Moves massless test particles one step under the pull of `sources` and `fields`.
+///
+/// Test particles feel the sources as a unit mass would but pull on nothing themselves, so this
+/// costs O(sources × tests) rather than growing with the square of all bodies.
+pub fn step_test_particles<'a, L, I>(
+    law: &L,
+    fields: &ExternalFields,
+    sources: I,
+    tests: &mut [Mass],
+) where
+    L: ForceLaw,
+    I: Iterator<Item = &'a Mass> + Clone,
+{
+    for t in tests.iter_mut() {
+        let mut acceleration = fields.acceleration(t.position);
+        for source in sources.clone() {
+            acceleration += law.test_acceleration(t.position, source);
+        }
+        t.velocity += acceleration;
+        t.position += t.velocity;
+    }
+}
+
+/// Removes the body with the given `id` from `masses`, keeping the order of the rest.
+fn remove_by_id(masses: &mut Vec<Mass>, id: u64) -> Option<Mass> {
+    let index = masses.iter().position(|x| x.id == id)?;
+    Some(masses.remove(index))
+}
+
 pub trait Simulator: Debug + Send + Sync {
     fn step(&mut self);
-    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &Mass> + 'a>;
+    /// Adds a background field felt by every body from the next step on.
+    fn add_field(&mut self, field: Box<dyn ExternalField>);
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a>;
+    /// The same bodies as `mass_iter`, in the same order, for editing between steps.
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a>;
+    /// Adds a body from the next step on; a massless one becomes a test particle.
+    fn add_mass(&mut self, mass: Mass);
+    /// Takes out the body with the given `id`, if there is one.
+    fn remove_mass(&mut self, id: u64) -> Option<Mass>;
+
+    fn find_mass(&self, id: u64) -> Option<&Mass> {
+        self.mass_iter().find(|x| x.id == id)
+    }
+
+    fn find_mass_mut(&mut self, id: u64) -> Option<&mut Mass> {
+        self.mass_iter_mut().find(|x| x.id == id)
+    }
+
+    /// Acceleration acting on the body with the given `id` under the engine's gravity and fields,
+    /// summed directly rather than as the engine approximates it. Engines without gravity, or
+    /// whose forces a direct sum doesn't give, such as periodic meshes, have none.
+    fn acceleration(&self, _id: u64) -> Option<Point> {
+        None
+    }
+
+    fn len(&self) -> usize {
+        self.mass_iter().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Length of the most recent step in simulation time; fixed step engines always take 1.
+    fn time_step(&self) -> Float {
+        1.0
+    }
+
+    /// Number of attempted steps thrown away by engines that control their own error.
+    fn rejected_steps(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use adaptive::*;
+    use block::*;
+    use electrostatic::*;
+    use fmm::*;
+    use hermite::*;
+    use joe::*;
+    use matt::*;
+    use no_gravity::*;
+    use p3m::*;
+    use pm::*;
+
+    fn factories() -> Vec<Box<dyn SimFactory>> {
+        vec![
+            Box::new(JoeFactory::<Logarithmic>::default()),
+            Box::new(JoeFactory {
+                law: Logarithmic::default(),
+                builder: TreeBuilder::KdMedian,
+                rebuild: Rebuild::Refit { min_balance: 0.5 },
+            }),
+            Box::new(MattFactory::<Newtonian>::default()),
+            Box::new(NoGravityFactory {}),
+            Box::new(ElectrostaticFactory::<Coulomb, Newtonian>::default()),
+            Box::new(ElectrostaticFactory {
+                electric: Coulomb::default(),
+                gravity: Some(Newtonian::default()),
+            }),
+            Box::new(AdaptiveFactory::<Newtonian>::default()),
+            Box::new(BlockFactory::<Newtonian>::default()),
+            Box::new(HermiteFactory::<Newtonian>::default()),
+            Box::new(PmFactory::<Logarithmic>::default()),
+            Box::new(P3mFactory::<Newtonian>::default()),
+            Box::new(FmmFactory::default()),
+        ]
+    }
+
+    fn body(x: Float, mass: Float) -> Mass {
+        Mass {
+            position: Point(x, 0.0),
+            velocity: Point::ZERO,
+            mass,
+            charge: 0.0,
+            id: Mass::new_id(),
+        }
+    }
+
+    #[test]
+    fn test_editing() {
+        for factory in factories() {
+            let name = factory.name();
+            let first = body(-10.0, 1.0);
+            let mut sim = factory.with_masses(vec![
+                first,
+                body(10.0, 2.0),
+                Mass::new_test_particle(Point(0.0, 10.0), Point::ZERO),
+            ]);
+            assert_eq!(sim.len(), 3, "{}", name);
+
+            sim.add_mass(body(0.0, 3.0));
+            sim.add_mass(Mass::new_test_particle(Point(0.0, -10.0), Point::ZERO));
+            assert_eq!(sim.len(), 5, "{}", name);
+
+            for x in sim.mass_iter_mut() {
+                x.velocity = Point(0.0, 0.5);
+            }
+            assert!(
+                sim.mass_iter().all(|x| x.velocity == Point(0.0, 0.5)),
+                "{}",
+                name
+            );
+
+            let removed = sim.remove_mass(first.id).unwrap();
+            assert_eq!(removed.mass, first.mass, "{}", name);
+            assert!(sim.remove_mass(first.id).is_none(), "{}", name);
+            assert_eq!(sim.len(), 4, "{}", name);
+
+            sim.step();
+            assert_eq!(sim.len(), 4, "{}", name);
+            assert!(sim.find_mass(first.id).is_none(), "{}", name);
+            assert_eq!(
+                sim.mass_iter().filter(|x| x.is_test_particle()).count(),
+                2,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let mut pulled = 0;
+        for factory in factories() {
+            let name = factory.name();
+            let left = body(-10.0, 1.0);
+            let test = Mass::new_test_particle(Point(0.0, 10.0), Point::ZERO);
+            let sim = factory.with_masses(vec![left, body(10.0, 2.0), test]);
+            assert!(sim.acceleration(u64::MAX).is_none(), "{}", name);
+            if let Some(a) = sim.acceleration(left.id) {
+                pulled += 1;
+                assert!(a.0 > 0.0, "{}", name);
+                let a = sim.acceleration(test.id).unwrap();
+                assert!(a.1 < 0.0, "{}", name);
+            }
+        }
+        assert_eq!(pulled, 9);
+        let sim = NoGravityFactory {}.with_masses(vec![body(0.0, 1.0)]);
+        assert!(sim
+            .acceleration(sim.mass_iter().next().unwrap().id)
+            .is_none());
+    }
+
+    #[test]
+    fn test_seeded_bodies() {
+        let factory = NoGravityFactory {};
+        let positions = |seed: u64| -> Vec<Point> {
+            let sim = factory.new_seeded(5, seed);
+            sim.mass_iter().map(|x| x.position).collect()
+        };
+        assert_eq!(positions(7), positions(7));
+        assert_ne!(positions(7), positions(8));
+    }
+
+    #[test]
+    fn test_ids_survive_steps() {
+        for factory in factories() {
+            let name = factory.name();
+            let mut masses: Vec<Mass> = (0..20).map(|_| Mass::new_random()).collect();
+            masses.push(Mass::new_test_particle(Point(5.0, 5.0), Point::ZERO));
+            let mut ids: Vec<u64> = masses.iter().map(|x| x.id).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            assert_eq!(ids.len(), masses.len());
+
+            let mut sim = factory.with_masses(masses.clone());
+            for _i in 0..3 {
+                sim.step();
+            }
+
+            let mut after: Vec<u64> = sim.mass_iter().map(|x| x.id).collect();
+            after.sort_unstable();
+            assert_eq!(after, ids, "{}", name);
+            for x in masses.iter() {
+                assert_eq!(sim.find_mass(x.id).unwrap().mass, x.mass, "{}", name);
+            }
+
+            let id = masses[3].id;
+            sim.find_mass_mut(id).unwrap().velocity = Point(1.0, 2.0);
+            assert_eq!(
+                sim.find_mass(id).unwrap().velocity,
+                Point(1.0, 2.0),
+                "{}",
+                name
+            );
+        }
+    }
 }
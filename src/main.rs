@@ -1,61 +1,531 @@
+use space::adaptive::*;
+use space::block::*;
+use space::electrostatic::*;
+use space::fmm::*;
+use space::force::*;
+use space::gif::*;
+use space::hermite::*;
 use space::joe::*;
 use space::matt::*;
 use space::no_gravity::*;
+use space::p3m::*;
+use space::pm::*;
+use space::recorder::*;
+use space::render::*;
+use space::svg::*;
+use space::view::*;
+use space::vtk::*;
 use space::*;
 
-fn select_factory() -> Box<dyn SimFactory> {
-    use std::env;
+const USAGE: &str = "usage: space [ENGINE] [--bodies N] [--steps N] [--seed N] \
+[--record FILE|-] [--format csv|ndjson|binary] [--vtk DIR] \
+[--frames DIR] [--image-format png|ppm] [--gif FILE] [--fps N] [--svg FILE] [--paths] \
+[--size WIDTHxHEIGHT] [--trails N] [--color-by auto|speed|mass|charge] [--every N] [--ids ID,ID,...]";
 
-    let default_sim_factory: Box<dyn SimFactory> = Box::new(MattFactory {});
-    let args: Vec<String> = env::args().collect();
+/// What the command line asked for. The engine is a number picked by `select_factory`.
+#[derive(Debug)]
+struct Options {
+    engine: Option<i32>,
+    bodies: usize,
+    steps: usize,
+    /// Seed the bodies are made from; a random one when not given.
+    seed: Option<u64>,
+    /// File to write trajectories to, or `-` for standard output.
+    record: Option<String>,
+    /// Overrides the format the record file's extension suggests.
+    format: Option<Format>,
+    /// Directory to write a VTK time series to.
+    vtk: Option<String>,
+    /// Directory to write numbered frames to.
+    frames: Option<String>,
+    image_format: ImageFormat,
+    /// File to write an animated GIF to.
+    gif: Option<String>,
+    /// Frames a second in the GIF.
+    fps: f64,
+    /// File to draw the final state to as an SVG.
+    svg: Option<String>,
+    /// Whether the SVG shows the path each body took.
+    paths: bool,
+    /// Width and height of rendered frames in pixels.
+    size: (usize, usize),
+    /// Past positions drawn behind each body in rendered frames and the viewer.
+    trails: usize,
+    color_by: ColorBy,
+    /// Only every `every`th step is written to files.
+    every: usize,
+    /// Bodies to record; ids count up from 1 in the order the bodies are made.
+    ids: Option<Vec<u64>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            engine: None,
+            bodies: 3,
+            steps: 10,
+            seed: None,
+            record: None,
+            format: None,
+            vtk: None,
+            frames: None,
+            image_format: ImageFormat::Png,
+            gif: None,
+            fps: 20.0,
+            svg: None,
+            paths: false,
+            size: (400, 400),
+            trails: 0,
+            color_by: ColorBy::Auto,
+            every: 1,
+            ids: None,
+        }
+    }
+}
 
-    // validate number of arguments with default behavior
-    if args.len() == 1 || args.len() > 2 {
-        return default_sim_factory;
+impl Options {
+    /// Whether to run without a viewer, only writing files.
+    fn exporting(&self) -> bool {
+        self.record.is_some()
+            || self.vtk.is_some()
+            || self.frames.is_some()
+            || self.gif.is_some()
+            || self.svg.is_some()
     }
+}
 
-    match args[1].parse::<i32>() {
-        Ok(1) => Box::new(JoeFactory {}),
-        Ok(2) => Box::new(MattFactory {}),
-        Ok(3) => Box::new(NoGravityFactory {}),
-        Ok(_) | Err(_) => default_sim_factory,
+fn parse_options<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    fn number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+        let value = value.ok_or(format!("{} needs a value", flag))?;
+        value
+            .parse()
+            .map_err(|_| format!("{} expects a number, not {}", flag, value))
+    }
+
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bodies" => options.bodies = number(&arg, args.next())?,
+            "--steps" => options.steps = number(&arg, args.next())?,
+            "--seed" => options.seed = Some(number(&arg, args.next())?),
+            "--every" => options.every = number(&arg, args.next())?,
+            "--record" => {
+                options.record = Some(args.next().ok_or("--record needs a file name")?);
+            }
+            "--vtk" => options.vtk = Some(args.next().ok_or("--vtk needs a directory")?),
+            "--frames" => {
+                options.frames = Some(args.next().ok_or("--frames needs a directory")?);
+            }
+            "--image-format" => {
+                let format = args.next().ok_or("--image-format needs a value")?;
+                options.image_format = format.parse()?;
+            }
+            "--size" => {
+                let size = args.next().ok_or("--size needs a value")?;
+                let mut sides = size.splitn(2, 'x').map(|side| Some(side.to_string()));
+                let width = number("--size", sides.next().flatten())?;
+                let height = number("--size", sides.next().flatten())?;
+                options.size = (width, height);
+            }
+            "--trails" => options.trails = number(&arg, args.next())?,
+            "--gif" => options.gif = Some(args.next().ok_or("--gif needs a file name")?),
+            "--fps" => options.fps = number(&arg, args.next())?,
+            "--svg" => options.svg = Some(args.next().ok_or("--svg needs a file name")?),
+            "--paths" => options.paths = true,
+            "--color-by" => {
+                let color_by = args.next().ok_or("--color-by needs a value")?;
+                options.color_by = color_by.parse()?;
+            }
+            "--format" => {
+                let format = args.next().ok_or("--format needs a value")?;
+                options.format = Some(format.parse()?);
+            }
+            "--ids" => {
+                let ids = args.next().ok_or("--ids needs a list of ids")?;
+                options.ids = Some(
+                    ids.split(',')
+                        .map(|id| number("--ids", Some(id.trim().to_string())))
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+            _ if options.engine.is_none() && !arg.starts_with("--") => {
+                options.engine = Some(number("engine", Some(arg))?);
+            }
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn options() -> Options {
+    parse_options(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{}\n{}", error, USAGE);
+        std::process::exit(2);
+    })
+}
+
+/// Bodies the options ask to write, or `None` for all of them.
+fn filter(options: &Options) -> Option<Filter> {
+    let ids = options.ids.clone()?;
+    Some(Box::new(move |x: &Mass| ids.contains(&x.id)))
+}
+
+fn recorder(options: &Options, path: &str) -> std::io::Result<Recorder<Box<dyn std::io::Write>>> {
+    use std::fs::File;
+    use std::io::{stdout, BufWriter, Error, ErrorKind, Write};
+
+    let format = match options.format.or_else(|| Format::from_path(path)) {
+        Some(format) => format,
+        None if path == "-" => Format::Csv,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("can't tell the format of {}, pass --format", path),
+            ))
+        }
+    };
+    let out: Box<dyn Write> = if path == "-" {
+        Box::new(BufWriter::new(stdout()))
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+
+    let mut recorder = Recorder::new(out, format);
+    recorder.every = options.every;
+    recorder.filter = filter(options);
+    Ok(recorder)
+}
+
+/// A renderer for frames of the size and look the options ask for.
+fn renderer(options: &Options) -> Renderer {
+    let mut renderer = Renderer::new(options.size.0, options.size.1);
+    renderer.trails = options.trails;
+    renderer.color_by = options.color_by;
+    renderer
+}
+
+/// Steps `sim` as the options ask without showing it, writing it to every output asked for.
+fn export(options: &Options, sim: &mut dyn Simulator) -> std::io::Result<()> {
+    let mut vtk = match options.vtk.as_deref() {
+        Some(dir) => {
+            let mut series = VtkSeries::new(dir, "space")?;
+            series.every = options.every;
+            series.filter = filter(options);
+            Some(series)
+        }
+        None => None,
+    };
+    let mut frames = match options.frames.as_deref() {
+        Some(dir) => {
+            let mut series =
+                FrameSeries::new(dir, "frame", options.image_format, renderer(options))?;
+            series.every = options.every;
+            Some(series)
+        }
+        None => None,
+    };
+    let mut gif = match options.gif.as_deref() {
+        Some(path) => {
+            let out = std::io::BufWriter::new(std::fs::File::create(path)?);
+            let mut animation = GifAnimation::new(out, renderer(options), options.fps)?;
+            animation.every = options.every;
+            Some(animation)
+        }
+        None => None,
+    };
+    let mut svg = options.svg.as_ref().map(|_| {
+        let mut picture = SvgPicture::new(options.size.0 as f64, options.size.1 as f64);
+        picture.color_by = options.color_by;
+        picture.paths = options.paths;
+        picture.every = options.every;
+        picture
+    });
+    let mut recorder = match options.record.as_deref() {
+        Some(path) => Some(recorder(options, path)?),
+        None => None,
+    };
+
+    for step in 0..=options.steps {
+        if step > 0 {
+            sim.step();
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.observe(sim)?;
+        }
+        if let Some(vtk) = vtk.as_mut() {
+            vtk.observe(sim)?;
+        }
+        if let Some(frames) = frames.as_mut() {
+            frames.observe(sim)?;
+        }
+        if let Some(gif) = gif.as_mut() {
+            gif.observe(sim)?;
+        }
+        if let Some(svg) = svg.as_mut() {
+            svg.observe(sim);
+        }
+    }
+
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.flush()?;
+    }
+    if let Some(vtk) = vtk {
+        vtk.finish()?;
+    }
+    if let Some(gif) = gif {
+        gif.finish()?;
+    }
+    if let (Some(picture), Some(path)) = (svg, options.svg.as_deref()) {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        picture.write(&mut out)?;
+        std::io::Write::flush(&mut out)?;
+    }
+    Ok(())
+}
+
+/// Exports `sim`, leaving with an error when any output can't be written.
+fn export_or_exit(options: &Options, sim: &mut dyn Simulator) {
+    if let Err(error) = export(options, sim) {
+        eprintln!("writing failed: {}", error);
+        std::process::exit(1);
+    }
+}
+
+/// Runs `stty` on the controlling terminal, returning what it prints.
+#[cfg(not(feature = "use_gtk"))]
+fn stty(args: &[&str]) -> Option<String> {
+    let tty = std::fs::File::open("/dev/tty").ok()?;
+    let output = std::process::Command::new("stty")
+        .args(args)
+        .stdin(tty)
+        .output()
+        .ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
+
+/// Keeps the terminal raw while it lives, so keys arrive as they are pressed and unechoed.
+#[cfg(not(feature = "use_gtk"))]
+struct RawTerminal {
+    saved: String,
+}
+
+#[cfg(not(feature = "use_gtk"))]
+impl RawTerminal {
+    fn enter() -> Option<RawTerminal> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Some(RawTerminal { saved })
+    }
+}
+
+#[cfg(not(feature = "use_gtk"))]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+/// Shows `sim` live in the terminal until the viewer is quit.
+#[cfg(not(feature = "use_gtk"))]
+fn watch(options: &Options, sim: &mut dyn Simulator) -> std::io::Result<()> {
+    use space::terminal::*;
+    use std::io::{stdin, stdout, Read, Write};
+    use std::time::{Duration, Instant};
+
+    let (rows, cols) = stty(&["size"])
+        .and_then(|size| {
+            let mut sides = size.split_whitespace().map(|side| side.parse().ok());
+            Some((sides.next()??, sides.next()??))
+        })
+        .unwrap_or((24, 80));
+    let mut view = TerminalView::new(cols, rows.saturating_sub(1).max(1));
+    view.color_by = options.color_by;
+
+    let _raw = RawTerminal::enter();
+    let (keys, pressed) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for byte in stdin().lock().bytes() {
+            match byte {
+                Ok(byte) if keys.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+
+    let mut out = stdout();
+    // hide the cursor and clear the screen
+    write!(out, "\x1b[?25l\x1b[2J")?;
+    let mut last = Instant::now();
+    'viewing: loop {
+        while let Ok(byte) = pressed.try_recv() {
+            if let Some(key) = Key::from_byte(byte) {
+                if !view.press(key) {
+                    break 'viewing;
+                }
+            }
+        }
+        let now = Instant::now();
+        view.advance(sim, (now - last).as_secs_f64());
+        last = now;
+        write!(out, "{}", view.draw(sim))?;
+        out.flush()?;
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    write!(out, "\x1b[0m\x1b[?25h\r\n")?;
+    out.flush()
+}
+
+fn select_factory(engine: Option<i32>) -> Box<dyn SimFactory> {
+    let default_sim_factory: Box<dyn SimFactory> = Box::new(MattFactory::<Newtonian>::default());
+    let engine = match engine {
+        Some(engine) => engine,
+        None => return default_sim_factory,
+    };
+
+    match engine {
+        1 => Box::new(JoeFactory::<Logarithmic>::default()),
+        2 => Box::new(MattFactory::<Newtonian>::default()),
+        3 => Box::new(NoGravityFactory {}),
+        4 => Box::new(ElectrostaticFactory {
+            electric: Coulomb::default(),
+            gravity: None::<Newtonian>,
+        }),
+        5 => Box::new(ElectrostaticFactory {
+            electric: Coulomb::default(),
+            gravity: Some(Newtonian::default()),
+        }),
+        6 => Box::new(AdaptiveFactory::<Newtonian>::default()),
+        7 => Box::new(BlockFactory::<Newtonian>::default()),
+        8 => Box::new(HermiteFactory::<Newtonian>::default()),
+        9 => Box::new(PmFactory::<Logarithmic>::default()),
+        10 => Box::new(P3mFactory::<Newtonian>::default()),
+        11 => Box::new(FmmFactory::default()),
+        12 => Box::new(MattFactory {
+            law: Newtonian::default(),
+            multipole: Multipole::Octupole,
+            neighbors: 8,
+        }),
+        13 => Box::new(JoeFactory {
+            law: Logarithmic::default(),
+            builder: TreeBuilder::KdMedian,
+            rebuild: Rebuild::Refit { min_balance: 0.5 },
+        }),
+        _ => default_sim_factory,
     }
 }
 
 #[cfg(not(feature = "use_gtk"))]
 pub fn main() {
-    let factory = select_factory();
-    let mut sim: Box<dyn Simulator> = factory.new(3);
-    println!("{:#?}", sim);
-    for _x in 0..10 {
-        std::thread::sleep(std::time::Duration::from_millis(1000));
-        sim.step();
+    let options = options();
+    let factory = select_factory(options.engine);
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let mut sim: Box<dyn Simulator> = factory.new_seeded(options.bodies, seed);
+    if options.exporting() {
+        export_or_exit(&options, &mut *sim);
+        return;
+    }
+
+    use std::io::IsTerminal;
+    if !std::io::stdout().is_terminal() {
         println!("{:#?}", sim);
+        for _x in 0..options.steps {
+            std::thread::sleep(std::time::Duration::from_millis(1000));
+            sim.step();
+            println!("{:#?}", sim);
+        }
+        return;
+    }
+    if let Err(error) = watch(&options, &mut *sim) {
+        eprintln!("viewing failed: {}", error);
+        std::process::exit(1);
+    }
+}
+
+/// Camera modes offered in the viewer, in the order of `camera_mode`.
+#[cfg(feature = "use_gtk")]
+const CAMERA_MODES: [&str; 5] = [
+    "Fit all",
+    "Fit 90% of mass",
+    "Follow center of mass",
+    "Follow selected body",
+    "Free",
+];
+
+#[cfg(feature = "use_gtk")]
+const FREE_CAMERA: u32 = 4;
+
+/// The camera mode picked from the list; following a selection needs a body selected.
+#[cfg(feature = "use_gtk")]
+fn camera_mode(index: Option<u32>, selected: Option<u64>) -> space::view::CameraMode {
+    use space::view::CameraMode;
+
+    match index {
+        Some(0) => CameraMode::FitAll,
+        Some(1) => CameraMode::FitMass(0.9),
+        Some(2) => CameraMode::CenterOfMass,
+        Some(3) => selected.map_or(CameraMode::Free, CameraMode::Body),
+        _ => CameraMode::Free,
     }
 }
 
+/// A press of the main mouse button on the picture, which is a click until it moves far enough
+/// to be a drag.
+#[cfg(feature = "use_gtk")]
+#[derive(Debug, Copy, Clone)]
+struct Drag {
+    start: space::point::Point,
+    last: space::point::Point,
+    moved: bool,
+}
+
 #[cfg(feature = "use_gtk")]
 pub fn main() {
     use gio::prelude::*;
     use gtk::prelude::*;
     use gtk::*;
-    use palette::{Gradient, Hsv, LinSrgb};
+    use space::control::*;
+    use space::point::Point;
+    use space::view::*;
     use std::cell::Cell;
+    use std::rc::Rc;
     use std::sync::*;
 
-    let factory = select_factory();
-    let sim = Arc::new(RwLock::new(factory.new(3)));
+    /// Positions kept behind each body when trails are turned on without `--trails`.
+    const TRAIL_LENGTH: usize = 50;
+
+    let options = options();
+    let color_by = options.color_by;
+    let show_trails = options.trails > 0;
+    let trail_length = if show_trails {
+        options.trails
+    } else {
+        TRAIL_LENGTH
+    };
+    let bodies = options.bodies;
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let factory = Rc::new(select_factory(options.engine));
+    // writing files needs no window
+    if options.exporting() {
+        let mut sim = factory.new_seeded(bodies, seed);
+        export_or_exit(&options, &mut *sim);
+        return;
+    }
+    let sim: Shared = Arc::new(RwLock::new(factory.new_seeded(bodies, seed)));
+    let (commands, received) = mpsc::channel();
     let sim1 = sim.clone();
-    std::thread::spawn(move || {
-        std::thread::sleep(std::time::Duration::from_millis(1000));
-        loop {
-            if let Ok(ref mut s) = sim1.write() {
-                //println!("simulation step");
-                s.step();
-            }
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-    });
+    // trails are recorded by the stepping thread so they keep one point a step at any rate
+    let mut runner = Runner::default();
+    runner.trails = Arc::new(Mutex::new(Trails::new(if show_trails {
+        trail_length
+    } else {
+        0
+    })));
+    let trails = runner.trails.clone();
+    std::thread::spawn(move || runner.run(&sim1, received));
 
     let application =
         Application::new(Some("com.github.gtk-rs.examples.basic"), Default::default())
@@ -68,45 +538,318 @@ pub fn main() {
         const HEIGHT: f64 = 400.0;
         window.set_default_size(WIDTH as i32, HEIGHT as i32);
 
+        let camera = Rc::new(Cell::new(Camera::default()));
+        let selected = Rc::new(Cell::new(None::<u64>));
+
+        let toolbar = gtk::Box::new(Orientation::Horizontal, 4);
+        let camera_modes = ComboBoxText::new();
+        for name in CAMERA_MODES.iter() {
+            camera_modes.append_text(name);
+        }
+        camera_modes.set_active(Some(0));
+        {
+            let camera = camera.clone();
+            let selected = selected.clone();
+            camera_modes.connect_changed(move |modes| {
+                let mut view = camera.get();
+                view.mode = camera_mode(modes.get_active(), selected.get());
+                camera.set(view);
+            });
+        }
+        toolbar.pack_start(&Label::new(Some("Camera")), false, false, 4);
+        toolbar.pack_start(&camera_modes, false, false, 0);
+
+        // the simulation starts out running at the runner's own pace
+        let runner = Runner::default();
+        let playing = Rc::new(Cell::new(runner.playing));
+        let play = Button::with_label("Pause");
+        {
+            let commands = commands.clone();
+            let playing = playing.clone();
+            play.connect_clicked(move |play| {
+                playing.set(!playing.get());
+                if playing.get() {
+                    let _ = commands.send(Control::Play);
+                    play.set_label("Pause");
+                } else {
+                    let _ = commands.send(Control::Pause);
+                    play.set_label("Play");
+                }
+            });
+        }
+        let step = Button::with_label("Step");
+        {
+            let commands = commands.clone();
+            let playing = playing.clone();
+            let play = play.clone();
+            step.connect_clicked(move |_| {
+                let _ = commands.send(Control::Step);
+                playing.set(false);
+                play.set_label("Play");
+            });
+        }
+        let rate = Scale::with_range(Orientation::Horizontal, 1.0, 100.0, 1.0);
+        rate.set_value(runner.rate);
+        rate.set_size_request(120, -1);
+        {
+            let commands = commands.clone();
+            rate.connect_value_changed(move |rate| {
+                let _ = commands.send(Control::Rate(rate.get_value()));
+            });
+        }
+        let reset = Button::with_label("Reset");
+        {
+            let commands = commands.clone();
+            let factory = factory.clone();
+            let selected = selected.clone();
+            let camera = camera.clone();
+            let modes = camera_modes.clone();
+            reset.connect_clicked(move |_| {
+                // the same seed makes the same bodies, though with new ids
+                let _ = commands.send(Control::Reset(factory.new_seeded(bodies, seed)));
+                selected.set(None);
+                let mut view = camera.get();
+                view.mode = camera_mode(modes.get_active(), None);
+                camera.set(view);
+            });
+        }
+        toolbar.pack_start(&play, false, false, 4);
+        toolbar.pack_start(&step, false, false, 0);
+        toolbar.pack_start(&Label::new(Some("Steps/s")), false, false, 4);
+        toolbar.pack_start(&rate, false, false, 0);
+        toolbar.pack_start(&reset, false, false, 4);
+
+        let trails_shown = CheckButton::with_label("Trails");
+        trails_shown.set_active(show_trails);
+        {
+            let trails = trails.clone();
+            trails_shown.connect_toggled(move |shown| {
+                let mut trails = match trails.lock() {
+                    Ok(trails) => trails,
+                    Err(_) => return,
+                };
+                trails.clear();
+                trails.length = if shown.get_active() { trail_length } else { 0 };
+            });
+        }
+        toolbar.pack_start(&trails_shown, false, false, 4);
+
         let frame = gtk::Frame::new(None);
         let area = DrawingArea::new();
+        area.add_events(
+            gdk::EventMask::SCROLL_MASK
+                | gdk::EventMask::BUTTON_PRESS_MASK
+                | gdk::EventMask::BUTTON_RELEASE_MASK
+                | gdk::EventMask::POINTER_MOTION_MASK,
+        );
+        let size = |area: &DrawingArea| {
+            (
+                area.get_allocated_width() as f64,
+                area.get_allocated_height() as f64,
+            )
+        };
+
+        // the wheel zooms about the pointer
+        {
+            let camera = camera.clone();
+            let modes = camera_modes.clone();
+            area.connect_scroll_event(move |area, event| {
+                let factor = match event.get_direction() {
+                    gdk::ScrollDirection::Up => 1.25,
+                    gdk::ScrollDirection::Down => 0.8,
+                    _ => return Inhibit(false),
+                };
+                let (x, y) = event.get_position();
+                let (width, height) = size(area);
+                let mut view = camera.get();
+                view.zoom_at(factor, Point(x, y), width, height);
+                camera.set(view);
+                if view.mode == CameraMode::Free {
+                    modes.set_active(Some(FREE_CAMERA));
+                }
+                area.queue_draw();
+                Inhibit(true)
+            });
+        }
+
+        // dragging pans, and a click selects the body under the pointer
+        let drag = Rc::new(Cell::new(None::<Drag>));
+        {
+            let drag = drag.clone();
+            area.connect_button_press_event(move |_, event| {
+                if event.get_button() == 1 {
+                    let (x, y) = event.get_position();
+                    let start = Point(x, y);
+                    drag.set(Some(Drag {
+                        start,
+                        last: start,
+                        moved: false,
+                    }));
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let drag = drag.clone();
+            let camera = camera.clone();
+            let modes = camera_modes.clone();
+            area.connect_motion_notify_event(move |area, event| {
+                if let Some(mut pressed) = drag.get() {
+                    let (x, y) = event.get_position();
+                    let at = Point(x, y);
+                    if !pressed.moved && (at - pressed.start).magnitude() > 3.0 {
+                        pressed.moved = true;
+                        modes.set_active(Some(FREE_CAMERA));
+                    }
+                    if pressed.moved {
+                        let mut view = camera.get();
+                        view.pan(at - pressed.last);
+                        camera.set(view);
+                        area.queue_draw();
+                    }
+                    pressed.last = at;
+                    drag.set(Some(pressed));
+                }
+                Inhibit(false)
+            });
+        }
+        {
+            let camera = camera.clone();
+            let selected = selected.clone();
+            let modes = camera_modes.clone();
+            let sim = sim.clone();
+            area.connect_button_release_event(move |area, event| {
+                match drag.take() {
+                    Some(pressed) if !pressed.moved && event.get_button() == 1 => {
+                        let (width, height) = size(area);
+                        if let Ok(s) = sim.read() {
+                            let masses: Vec<&Mass> = s.mass_iter().collect();
+                            let view = camera.get();
+                            selected.set(view.nearest(&masses, pressed.start, 10.0, width, height));
+                        }
+                        // a new selection is followed straight away if that's the mode
+                        let mut view = camera.get();
+                        view.mode = camera_mode(modes.get_active(), selected.get());
+                        camera.set(view);
+                        area.queue_draw();
+                    }
+                    _ => {}
+                }
+                Inhibit(false)
+            });
+        }
 
-        let grad = Gradient::new(vec![
-            Hsv::from(LinSrgb::new(1.0, 0.0, 0.0)),
-            Hsv::from(LinSrgb::new(0.0, 1.0, 0.0)),
-        ]);
+        // the side panel tells about the selected body, refreshed with every frame
+        let panel = Grid::new();
+        panel.set_row_spacing(4);
+        panel.set_column_spacing(8);
+        panel.set_margin_start(8);
+        panel.set_margin_end(8);
+        let heading = Label::new(Some("Click a body to inspect it"));
+        panel.attach(&heading, 0, 0, 2, 1);
+        let values: Vec<Label> = INSPECTED
+            .iter()
+            .enumerate()
+            .map(|(row, name)| {
+                let name = Label::new(Some(name));
+                name.set_xalign(0.0);
+                let value = Label::new(None);
+                value.set_xalign(1.0);
+                value.set_selectable(true);
+                panel.attach(&name, 0, row as i32 + 1, 1, 1);
+                panel.attach(&value, 1, row as i32 + 1, 1, 1);
+                value
+            })
+            .collect();
 
         let sim2 = sim.clone();
-        let max_values = Cell::<(f64, f64)>::new((0.0, 0.0));
-        area.connect_draw(move |window, cairo| {
-            let width = window.get_allocated_width() as f64;
-            let height = window.get_allocated_height() as f64;
+        let trails = trails.clone();
+        let scale = Cell::new(AutoScale::default());
+        area.connect_draw(move |area, cairo| {
+            let (width, height) = size(area);
             if let Ok(s) = sim2.read() {
                 let i: Vec<&Mass> = s.mass_iter().collect();
-                let (mut size, mut speed) = max_values.get();
-                println!("draw max size: {}, max speed: {}", size, speed);
+                let mut view = camera.get();
+                view.update(&i, width, height);
+                camera.set(view);
+                // the camera frames the bodies; the scale only keeps colors steady
+                let mut fit = scale.get();
+                fit.update(i.iter().cloned());
+                let color_by = color_by.pick(i.iter().cloned());
 
-                for m in i.iter() {
-                    size = size.max(m.position.0.abs()).max(m.position.1.abs());
-                    speed = speed.max(m.velocity.magnitude());
+                // trails go underneath, each a line fading out towards its oldest end
+                cairo.set_line_width(1.0);
+                if let Ok(trails) = trails.lock() {
+                    for m in i.iter() {
+                        let color = body_color(m, color_by, &fit);
+                        let mut from = None;
+                        for (position, alpha) in trails.trail(m.id) {
+                            let p = view.to_screen(position, width, height);
+                            if let Some(from) = from {
+                                let Point(x, y) = from;
+                                cairo.set_source_rgba(color.red, color.green, color.blue, alpha);
+                                cairo.move_to(x, y);
+                                cairo.line_to(p.0, p.1);
+                                cairo.stroke();
+                            }
+                            from = Some(p);
+                        }
+                        if let Some(Point(x, y)) = from {
+                            let p = view.to_screen(m.position, width, height);
+                            cairo.set_source_rgb(color.red, color.green, color.blue);
+                            cairo.move_to(x, y);
+                            cairo.line_to(p.0, p.1);
+                            cairo.stroke();
+                        }
+                    }
                 }
+
                 for m in i.iter() {
-                    let x = (m.position.0 * width * 0.95 / size / 2.0) + (width / 2.0);
-                    let y = (m.position.1 * height * 0.95 / size / 2.0) + (height / 2.0);
-                    let size = m.mass * 10.0;
-                    let color: LinSrgb<f64> = grad.get(m.velocity.magnitude() / speed).into();
+                    let p = view.to_screen(m.position, width, height);
+                    let size = body_size(m);
+                    let color = body_color(m, color_by, &fit);
 
                     cairo.set_source_rgb(color.red, color.green, color.blue);
-                    cairo.rectangle(x, y, size, size);
+                    cairo.rectangle(p.0 - size / 2.0, p.1 - size / 2.0, size, size);
                     cairo.fill();
+
+                    if selected.get() == Some(m.id) {
+                        cairo.set_source_rgb(1.0, 1.0, 1.0);
+                        cairo.set_line_width(1.5);
+                        cairo.arc(p.0, p.1, size + 4.0, 0.0, 2.0 * std::f64::consts::PI);
+                        cairo.stroke();
+                    }
+                }
+                scale.set(fit);
+
+                // a body that is gone, say after a reset, is no longer selected
+                let shown = selected.get().and_then(|id| {
+                    let m = s.find_mass(id)?;
+                    Some(inspect(m, s.acceleration(id)))
+                });
+                if shown.is_none() {
+                    selected.set(None);
+                }
+                heading.set_text(if shown.is_some() {
+                    "Selected body"
+                } else {
+                    "Click a body to inspect it"
+                });
+                for (i, value) in values.iter().enumerate() {
+                    value.set_text(shown.as_ref().map_or("", |shown| shown[i].as_str()));
                 }
-                max_values.set((size, speed));
-                //println!("draw max size: {}, max speed: {}", size, speed);
             }
             gtk::Inhibit(false)
         });
+
         frame.add(&area);
-        window.add(&frame);
+        let body = gtk::Box::new(Orientation::Horizontal, 0);
+        body.pack_start(&frame, true, true, 0);
+        body.pack_start(&panel, false, false, 0);
+        let layout = gtk::Box::new(Orientation::Vertical, 0);
+        layout.pack_start(&toolbar, false, false, 2);
+        layout.pack_start(&body, true, true, 0);
+        window.add(&layout);
         window.show_all();
 
         glib::source::timeout_add_local(50, move || {
@@ -117,3 +860,60 @@ pub fn main() {
 
     application.run(&[]);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_options(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse(&[
+            "9", "--steps", "50", "--record", "out.bin", "--vtk", "out", "--every", "5", "--ids",
+            "1, 3",
+        ])
+        .unwrap();
+        assert_eq!(options.engine, Some(9));
+        assert_eq!(options.steps, 50);
+        assert_eq!(options.bodies, 3);
+        assert_eq!(options.record.as_deref(), Some("out.bin"));
+        assert_eq!(options.vtk.as_deref(), Some("out"));
+        assert_eq!(options.every, 5);
+        assert_eq!(options.ids, Some(vec![1, 3]));
+        assert!(options.exporting());
+
+        let options = parse(&[
+            "--frames",
+            "out",
+            "--size",
+            "640x480",
+            "--image-format",
+            "ppm",
+        ])
+        .unwrap();
+        assert_eq!(options.size, (640, 480));
+        assert_eq!(options.image_format, ImageFormat::Ppm);
+        assert!(!parse(&[]).unwrap().exporting());
+
+        let options = parse(&["--gif", "out.gif", "--fps", "12.5", "--color-by", "mass"]).unwrap();
+        assert!(options.exporting());
+        assert_eq!(options.fps, 12.5);
+        assert_eq!(options.color_by, ColorBy::Mass);
+
+        let options = parse(&["--svg", "out.svg", "--paths"]).unwrap();
+        assert!(options.exporting());
+        assert!(options.paths);
+
+        assert_eq!(parse(&["--seed", "42"]).unwrap().seed, Some(42));
+        assert_eq!(parse(&[]).unwrap().seed, None);
+
+        assert!(parse(&["--steps"]).is_err());
+        assert!(parse(&["--steps", "many"]).is_err());
+        assert!(parse(&["--format", "xml"]).is_err());
+        assert!(parse(&["1", "2"]).is_err());
+        assert!(parse(&["--size", "640"]).is_err());
+    }
+}
@@ -1,23 +1,60 @@
 use super::*;
 
-#[derive(Debug)]
-pub struct MattFactory;
+/// Builds Matt's center of mass simulator; inverse-square gravity by default. Each step finds
+/// every body's acceleration from where the bodies are, then kicks and drifts them all.
+#[derive(Debug, Default)]
+pub struct MattFactory<L: ForceLaw = Newtonian> {
+    pub law: L,
+    /// Moments of the cloud beyond its total, for bodies it doesn't surround.
+    pub multipole: Multipole,
+    /// Number of nearest bodies each body feels directly rather than as part of the cloud.
+    pub neighbors: usize,
+}
+
+/// How far the expansion of the rest of the cloud around its center of mass is taken.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Multipole {
+    /// The cloud as a single body at its center of mass.
+    #[default]
+    Monopole,
+    /// Adds the dipole and quadrupole terms, the first that see the cloud's shape.
+    Quadrupole,
+    /// Adds the octupole terms too.
+    Octupole,
+}
+
+impl Multipole {
+    fn order(self) -> usize {
+        match self {
+            Multipole::Monopole => 0,
+            Multipole::Quadrupole => 2,
+            Multipole::Octupole => 3,
+        }
+    }
+}
 
-impl SimFactory for MattFactory {
-    fn new(&self, count: usize) -> Box<dyn Simulator> {
-        let mut masses: Vec<Mass> = Vec::new();
+impl<L: ForceLaw + Clone + 'static> SimFactory for MattFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        let (masses, tests): (Vec<Mass>, Vec<Mass>) =
+            masses.into_iter().partition(|m| !m.is_test_particle());
         let mut cm_numerator = Point::ZERO;
         let mut cm_denominator = 0.0;
-        for _i in 0..count {
-            let tmp = Mass::new_random();
+        let mut coupling = 0.0;
+        for tmp in masses.iter() {
             cm_numerator += tmp.position * tmp.mass;
             cm_denominator += tmp.mass;
-            masses.push(tmp);
+            coupling += self.law.coupling(tmp);
         }
         Box::new(MattSimulator {
             masses,
+            tests,
             cm_numerator,
             cm_denominator,
+            coupling,
+            law: self.law.clone(),
+            fields: ExternalFields::default(),
+            multipole: self.multipole,
+            neighbors: self.neighbors,
         })
     }
 
@@ -27,51 +64,346 @@ impl SimFactory for MattFactory {
 }
 
 #[derive(Debug)]
-struct MattSimulator {
+struct MattSimulator<L: ForceLaw> {
     masses: Vec<Mass>,
+    tests: Vec<Mass>,
     cm_numerator: Point,
     cm_denominator: Float,
+    coupling: Float,
+    law: L,
+    fields: ExternalFields,
+    multipole: Multipole,
+    neighbors: usize,
 }
 
-impl MattSimulator {}
+/// What the corrections to the monopole need, found before anything moves.
+#[derive(Debug)]
+struct Cloud {
+    /// Moments of the whole cloud about `center`.
+    moments: Moments,
+    center: Point,
+    /// Indices of the bodies each body feels directly.
+    neighbors: Vec<Vec<usize>>,
+}
 
-impl Simulator for MattSimulator {
+impl<L: ForceLaw> MattSimulator<L> {
+    fn update_center_of_mass(&mut self) {
+        self.cm_numerator = Point::ZERO;
+        self.cm_denominator = 0.0;
+        self.coupling = 0.0;
+        for x in self.masses.iter() {
+            self.cm_numerator += x.position * x.mass;
+            self.cm_denominator += x.mass;
+            self.coupling += self.law.coupling(x);
+        }
+    }
+
+    fn cloud(&self) -> Cloud {
+        let center = self.cm_numerator / self.cm_denominator;
+        let mut moments = Moments::default();
+        for x in self.masses.iter() {
+            moments.add(x.position - center, self.law.coupling(x));
+        }
+        let positions: Vec<Point> = self.masses.iter().map(|x| x.position).collect();
+        Cloud {
+            moments,
+            center,
+            neighbors: nearest_neighbors(&positions, self.neighbors),
+        }
+    }
+}
+
+/// `Σ q s_x^i s_y^j` over bodies of coupling `q` at offsets `s` from some center, for
+/// `i + j <= 3`.
+#[derive(Debug, Copy, Clone, Default)]
+struct Moments([[Float; 4]; 4]);
+
+impl Moments {
+    fn add(&mut self, offset: Point, coupling: Float) {
+        for i in 0..4 {
+            for j in 0..4 - i {
+                self.0[i][j] += coupling * offset.0.powi(i as i32) * offset.1.powi(j as i32);
+            }
+        }
+    }
+
+    /// The same moments about a center `by` from the current one.
+    fn shifted(&self, by: Point) -> Moments {
+        let binomial = [
+            [1.0, 0.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0, 0.0],
+            [1.0, 2.0, 1.0, 0.0],
+            [1.0, 3.0, 3.0, 1.0],
+        ];
+        let mut shifted = Moments::default();
+        for i in 0..4 {
+            for j in 0..4 - i {
+                for a in 0..=i {
+                    for b in 0..=j {
+                        shifted.0[i][j] += binomial[i][a]
+                            * binomial[j][b]
+                            * (-by.0).powi((i - a) as i32)
+                            * (-by.1).powi((j - b) as i32)
+                            * self.0[a][b];
+                    }
+                }
+            }
+        }
+        shifted
+    }
+}
+
+/// Central difference weights for derivatives of orders zero through three, by sample offset.
+const STENCILS: [&[(i32, Float)]; 4] = [
+    &[(0, 1.0)],
+    &[(-1, -0.5), (1, 0.5)],
+    &[(-1, 1.0), (0, -2.0), (1, 1.0)],
+    &[(-2, -0.5), (-1, 1.0), (1, -1.0), (2, 0.5)],
+];
+
+/// Terms of orders one through `order` in the Taylor expansion of the force on a body of
+/// `coupling` from a cloud with `moments` about a center `offset` from the body. The derivatives
+/// of the law's force are taken numerically, so any law will do.
+fn multipole_correction<L: ForceLaw>(
+    law: &L,
+    order: usize,
+    coupling: Float,
+    offset: Point,
+    moments: &Moments,
+) -> Point {
+    let h = 1e-2 * offset.magnitude();
+    if order == 0 || h == 0.0 {
+        return Point::ZERO;
+    }
+    let unit = |dx: i32, dy: i32| {
+        law.force(
+            coupling,
+            Point::ZERO,
+            1.0,
+            offset + Point(dx as Float, dy as Float) * h,
+        )
+    };
+
+    let factorial = [1.0, 1.0, 2.0, 6.0];
+    let mut correction = Point::ZERO;
+    for n in 1..=order {
+        for i in 0..=n {
+            let j = n - i;
+            let mut derivative = Point::ZERO;
+            for (dx, wx) in STENCILS[i].iter() {
+                for (dy, wy) in STENCILS[j].iter() {
+                    derivative += unit(*dx, *dy) * (wx * wy);
+                }
+            }
+            correction +=
+                derivative * (moments.0[i][j] / (h.powi(n as i32) * factorial[i] * factorial[j]));
+        }
+    }
+    correction
+}
+
+/// The `k` bodies closest to each of `positions`, found by searching outward through a grid of
+/// about `k` bodies per cell.
+fn nearest_neighbors(positions: &[Point], k: usize) -> Vec<Vec<usize>> {
+    let k = k.min(positions.len().saturating_sub(1));
+    if k == 0 {
+        return vec![Vec::new(); positions.len()];
+    }
+
+    let mut min = Point(Float::MAX, Float::MAX);
+    let mut max = Point(Float::MIN, Float::MIN);
+    for p in positions.iter() {
+        min = Point(min.0.min(p.0), min.1.min(p.1));
+        max = Point(max.0.max(p.0), max.1.max(p.1));
+    }
+    let size = (max.0 - min.0).max(max.1 - min.1).max(Point::EPSILON);
+    let side = ((positions.len() as Float / k as Float).sqrt().ceil() as usize).max(1);
+    let width = size * (1.0 + 1e-9) / side as Float;
+    let cell = |p: Point| {
+        let coordinate = |x: Float| ((x / width) as i64).min(side as i64 - 1);
+        (coordinate(p.0 - min.0), coordinate(p.1 - min.1))
+    };
+    let mut cells: Vec<Vec<usize>> = vec![Vec::new(); side * side];
+    for (i, p) in positions.iter().enumerate() {
+        let (x, y) = cell(*p);
+        cells[y as usize * side + x as usize].push(i);
+    }
+
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let (x, y) = cell(*p);
+            let mut best: Vec<(Float, usize)> = Vec::with_capacity(k + 1);
+            for ring in 0..side as i64 {
+                // nothing this many cells out can beat the kth closest so far
+                if best.len() == k && best[k - 1].0 <= ((ring - 1) as Float * width).powi(2) {
+                    break;
+                }
+                for dy in -ring..=ring {
+                    for dx in -ring..=ring {
+                        let (cx, cy) = (x + dx, y + dy);
+                        let outside = cx < 0 || cy < 0 || cx >= side as i64 || cy >= side as i64;
+                        if dx.abs().max(dy.abs()) != ring || outside {
+                            continue;
+                        }
+                        for j in cells[cy as usize * side + cx as usize].iter() {
+                            if *j != i {
+                                best.push(((positions[*j] - *p).magnitude_squared(), *j));
+                            }
+                        }
+                    }
+                }
+                best.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                best.truncate(k);
+            }
+            best.into_iter().map(|(_, j)| j).collect()
+        })
+        .collect()
+}
+
+impl<L: ForceLaw> Simulator for MattSimulator<L> {
     fn step(&mut self) {
-        // update each mass
-        for x in self.masses.iter_mut() {
-            // update position based on current velocity
-            x.position += x.velocity;
+        // bodies may have been edited since the last step
+        self.update_center_of_mass();
+
+        // test particles feel every mass directly, before any of them move
+        step_test_particles(&self.law, &self.fields, self.masses.iter(), &mut self.tests);
+
+        // corrections see the cloud as it was before this step
+        let cloud = if self.multipole != Multipole::Monopole || self.neighbors > 0 {
+            Some(self.cloud())
+        } else {
+            None
+        };
+        let law = &self.law;
+        let count = self.masses.len();
+        let order = self.multipole.order();
+
+        // every force is found from where the bodies were before any of them move
+        let mut accelerations = Vec::with_capacity(count);
+        for (i, x) in self.masses.iter().enumerate() {
+            let mut acceleration = self.fields.acceleration(x.position);
 
-            // update velocity based on gravity effect
             // center of mass updated to exclude this particular mass
-            let cm = (self.cm_numerator - (x.position * x.mass)) / (self.cm_denominator - x.mass);
+            let coupling = law.coupling(x);
+            let mut rest = self.cm_denominator - x.mass;
+            let mut numerator = self.cm_numerator - (x.position * x.mass);
+            let mut rest_coupling = self.coupling - coupling;
+            let mut outside = count - 1;
+
+            // the nearest neighbors pull directly, and are taken out of the cloud
+            let neighbors: &[usize] = match &cloud {
+                Some(cloud) => &cloud.neighbors[i],
+                None => &[],
+            };
+            for j in neighbors.iter() {
+                let y = &self.masses[*j];
+                let force = law.force(coupling, x.position, law.coupling(y), y.position);
+                acceleration += force / x.mass;
+                rest -= y.mass;
+                numerator -= y.position * y.mass;
+                rest_coupling -= law.coupling(y);
+                outside -= 1;
+            }
+
+            if rest <= 0.0 || outside == 0 {
+                // nothing else in the cloud to pull on this mass
+                accelerations.push(acceleration);
+                continue;
+            }
+            let cm = numerator / rest;
 
-            // magnitude of force felt by this mass = this_mass * other_mass / distance**2
-            let force =
-                (x.mass * (self.cm_denominator - x.mass)) / (x.position - cm).magnitude_squared();
+            // force felt by this mass from the rest of the cloud lumped at its center of mass
+            let force = law.force(coupling, x.position, rest_coupling, cm);
 
-            // acceleration (change in velocity) is force / mass along the vector between the mass
-            // and the center of mass of the cloud
-            x.velocity += (cm - x.position).unit_vector() * (force / x.mass);
+            // acceleration (change in velocity) is force / mass
+            acceleration += force / x.mass;
+
+            // the shape of the rest of the cloud, from its moments about that center of mass
+            if let Some(cloud) = cloud.as_ref().filter(|_| order > 0) {
+                let mut moments = cloud.moments;
+                for j in neighbors.iter().chain(once(&i)) {
+                    let y = &self.masses[*j];
+                    moments.add(y.position - cloud.center, -law.coupling(y));
+                }
+                let moments = moments.shifted(cm - cloud.center);
+                let correction =
+                    multipole_correction(law, order, coupling, cm - x.position, &moments);
+                acceleration += correction / x.mass;
+            }
+            accelerations.push(acceleration);
         }
 
-        // update the center of mass of the collection
-        self.cm_numerator = Point::ZERO;
-        self.cm_denominator = 0.0;
-        for x in self.masses.iter() {
-            self.cm_numerator += x.position * x.mass;
-            self.cm_denominator += x.mass;
+        // then each mass is kicked and drifts
+        for (x, acceleration) in self.masses.iter_mut().zip(accelerations) {
+            x.velocity += acceleration;
+            x.position += x.velocity;
         }
     }
 
-    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &Mass> + 'a> {
-        Box::new(self.masses.iter())
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter().chain(self.tests.iter()))
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut().chain(self.tests.iter_mut()))
+    }
+
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        acceleration_of(&self.law, &self.fields, &self.masses, &self.tests, id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        if mass.is_test_particle() {
+            self.tests.push(mass);
+        } else {
+            self.masses.push(mass);
+        }
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id).or_else(|| remove_by_id(&mut self.tests, id))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn random_masses(count: usize, spread: Point) -> Vec<Mass> {
+        let mut rng = StdRng::seed_from_u64(5);
+        (0..count)
+            .map(|_| Mass {
+                position: Point(
+                    rng.gen_range(-spread.0..spread.0),
+                    rng.gen_range(-spread.1..spread.1),
+                ),
+                velocity: Point::ZERO,
+                mass: rng.gen_range(Mass::MIN_RANDOM_MASS..1.0),
+                charge: 0.0,
+                id: 0,
+            })
+            .collect()
+    }
+
+    /// Error in the first step's change of velocity of each body, against direct summation.
+    fn errors(factory: &MattFactory<Newtonian>, masses: &[Mass]) -> Vec<Float> {
+        let direct = accelerations(&factory.law, &ExternalFields::default(), masses);
+        let mut sim = factory.with_masses(masses.to_vec());
+        sim.step();
+        sim.mass_iter()
+            .zip(masses.iter())
+            .zip(direct)
+            .map(|((x, start), a)| (x.velocity - start.velocity - a).magnitude() / a.magnitude())
+            .collect()
+    }
 
     #[test]
     fn test_velocity() {
@@ -79,11 +411,19 @@ mod test {
             position: Point::ZERO,
             velocity: Point(1.0, 1.0),
             mass: 1.0,
+            charge: 0.0,
+            id: 0,
         };
         let mut sim = MattSimulator {
             masses: vec![test_mass],
+            tests: Vec::new(),
             cm_numerator: test_mass.position * test_mass.mass,
             cm_denominator: test_mass.mass,
+            coupling: test_mass.mass,
+            law: Newtonian::default(),
+            fields: ExternalFields::default(),
+            multipole: Multipole::Monopole,
+            neighbors: 0,
         };
 
         sim.step();
@@ -98,6 +438,8 @@ mod test {
             position: Point(-1.0, 0.0),
             velocity: Point::ZERO,
             mass: 1.0,
+            charge: 0.0,
+            id: 0,
         };
         let test_mass2 = Mass {
             position: Point(1.0, 0.0),
@@ -105,9 +447,15 @@ mod test {
         };
         let mut sim = MattSimulator {
             masses: vec![test_mass1, test_mass2],
+            tests: Vec::new(),
             cm_numerator: (test_mass1.position * test_mass1.mass)
                 + (test_mass2.position * test_mass2.mass),
             cm_denominator: test_mass1.mass + test_mass2.mass,
+            coupling: test_mass1.mass + test_mass2.mass,
+            law: Newtonian::default(),
+            fields: ExternalFields::default(),
+            multipole: Multipole::Monopole,
+            neighbors: 0,
         };
 
         sim.step();
@@ -128,4 +476,148 @@ mod test {
         assert!(sim.masses[0].position.1 == 0.0);
         assert!(sim.masses[1].position.1 == 0.0);
     }
+
+    #[test]
+    fn test_kick_then_drift() {
+        let law = Newtonian::default();
+        let body = |x: Float, vy: Float| Mass {
+            position: Point(x, 0.0),
+            velocity: Point(0.0, vy),
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let start = vec![body(-2.0, 0.5), body(2.0, -0.5)];
+        let mut sim = MattFactory {
+            law,
+            ..MattFactory::default()
+        }
+        .with_masses(start.clone());
+        sim.step();
+
+        // the pull is found where the bodies started, and each moves at its new velocity; bodies
+        // once drifted first and were pulled from where they landed
+        let direct = accelerations(&law, &ExternalFields::default(), &start);
+        for ((x, before), a) in sim.mass_iter().zip(start.iter()).zip(direct) {
+            assert!((x.velocity - (before.velocity + a)).magnitude() < 1e-12);
+            assert_eq!(x.position, before.position + x.velocity);
+        }
+    }
+
+    #[test]
+    fn test_test_particle() {
+        let test_mass = Mass {
+            position: Point(1.0, 0.0),
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let factory = MattFactory::<Newtonian>::default();
+        let mut sim = factory.with_masses(vec![
+            test_mass,
+            Mass::new_test_particle(Point(-1.0, 0.0), Point::ZERO),
+        ]);
+
+        sim.step();
+
+        // the test particle is pulled in, but doesn't pull back
+        let masses: Vec<&Mass> = sim.mass_iter().collect();
+        assert!(masses[0].velocity == Point::ZERO);
+        assert!(masses[0].position == Point(1.0, 0.0));
+        assert!(masses[1].is_test_particle());
+        assert!(masses[1].velocity.0 > 0.0);
+        assert!(masses[1].velocity.1 == 0.0);
+    }
+
+    #[test]
+    fn test_moments_shifted() {
+        let masses = random_masses(10, Point(3.0, 1.0));
+        let mut about_origin = Moments::default();
+        let mut about_elsewhere = Moments::default();
+        let elsewhere = Point(1.5, -2.0);
+        for x in masses.iter() {
+            about_origin.add(x.position, x.mass);
+            about_elsewhere.add(x.position - elsewhere, x.mass);
+        }
+
+        let shifted = about_origin.shifted(elsewhere);
+        for i in 0..4 {
+            for j in 0..4 - i {
+                assert!((shifted.0[i][j] - about_elsewhere.0[i][j]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_neighbors() {
+        let masses = random_masses(200, Point(50.0, 20.0));
+        let positions: Vec<Point> = masses.iter().map(|x| x.position).collect();
+        let found = nearest_neighbors(&positions, 5);
+
+        for (i, p) in positions.iter().enumerate() {
+            let mut expected: Vec<usize> = (0..positions.len()).filter(|j| *j != i).collect();
+            expected.sort_by(|a, b| {
+                let a = (positions[*a] - *p).magnitude_squared();
+                let b = (positions[*b] - *p).magnitude_squared();
+                a.partial_cmp(&b).unwrap()
+            });
+            expected.truncate(5);
+            assert_eq!(found[i], expected);
+        }
+    }
+
+    #[test]
+    fn test_multipoles_improve_accuracy() {
+        // a body well outside a lopsided, elongated cloud
+        let mut masses = vec![Mass {
+            position: Point(60.0, 25.0),
+            ..Mass::new_test_particle(Point::ZERO, Point::ZERO)
+        }];
+        masses[0].mass = 1.0;
+        masses.extend(
+            random_masses(50, Point(8.0, 2.0))
+                .into_iter()
+                .map(|x| Mass {
+                    position: Point((x.position.0 + 8.0).powi(2) / 16.0, x.position.1),
+                    ..x
+                }),
+        );
+
+        let error = |multipole| {
+            let factory = MattFactory {
+                law: Newtonian::default(),
+                multipole,
+                neighbors: 0,
+            };
+            errors(&factory, &masses)[0]
+        };
+        let monopole = error(Multipole::Monopole);
+        let quadrupole = error(Multipole::Quadrupole);
+        let octupole = error(Multipole::Octupole);
+        assert!(quadrupole * 5.0 < monopole, "{} {}", quadrupole, monopole);
+        assert!(octupole * 2.0 < quadrupole, "{} {}", octupole, quadrupole);
+    }
+
+    #[test]
+    fn test_every_neighbor_is_direct_summation() {
+        let masses = random_masses(20, Point(10.0, 10.0));
+        let factory = MattFactory {
+            law: Newtonian::default(),
+            multipole: Multipole::Octupole,
+            neighbors: 19,
+        };
+        for error in errors(&factory, &masses) {
+            assert!(error < 1e-9);
+        }
+
+        // moving bodies are all pulled from where they started the step
+        let mut moving = masses;
+        for (i, x) in moving.iter_mut().enumerate() {
+            x.velocity = Point(i as Float * 0.3 - 3.0, 1.5 - i as Float * 0.2);
+        }
+        for error in errors(&factory, &moving) {
+            assert!(error < 1e-9);
+        }
+    }
 }
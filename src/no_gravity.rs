@@ -4,12 +4,11 @@ use super::*;
 pub struct NoGravityFactory;
 
 impl SimFactory for NoGravityFactory {
-    fn new(&self, count: usize) -> Box<dyn Simulator> {
-        let mut masses: Vec<Mass> = Vec::new();
-        for _i in 0..count {
-            masses.push(Mass::new_random());
-        }
-        Box::new(NoGravitySimulator { masses })
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        Box::new(NoGravitySimulator {
+            masses,
+            fields: ExternalFields::default(),
+        })
     }
 
     fn name(&self) -> String {
@@ -20,6 +19,7 @@ impl SimFactory for NoGravityFactory {
 #[derive(Debug)]
 struct NoGravitySimulator {
     masses: Vec<Mass>,
+    fields: ExternalFields,
 }
 
 impl NoGravitySimulator {}
@@ -27,13 +27,30 @@ impl NoGravitySimulator {}
 impl Simulator for NoGravitySimulator {
     fn step(&mut self) {
         for x in self.masses.iter_mut() {
+            x.velocity += self.fields.acceleration(x.position);
             x.position += x.velocity;
         }
     }
 
-    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &Mass> + 'a> {
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
         Box::new(self.masses.iter())
     }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut())
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.masses.push(mass);
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id)
+    }
 }
 
 #[cfg(test)]
@@ -46,9 +63,12 @@ mod test {
             position: Point::ZERO,
             velocity: Point(1.0, 1.0),
             mass: 1.0,
+            charge: 0.0,
+            id: 0,
         };
         let mut sim = NoGravitySimulator {
             masses: vec![test_mass],
+            fields: ExternalFields::default(),
         };
 
         sim.step();
@@ -63,4 +83,28 @@ mod test {
         assert!((sim.masses[0].position - Point(3.0, 3.0)).magnitude_squared() < Point::EPSILON);
         assert!(sim.masses[0].position == Point(3.0, 3.0));
     }
+
+    #[test]
+    fn test_uniform_field() {
+        let test_mass = Mass {
+            position: Point::ZERO,
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        };
+        let mut sim = NoGravitySimulator {
+            masses: vec![test_mass],
+            fields: ExternalFields::default(),
+        };
+        sim.add_field(Box::new(Uniform(Point(0.0, -1.0))));
+
+        sim.step();
+        assert!(sim.masses[0].velocity == Point(0.0, -1.0));
+        assert!(sim.masses[0].position == Point(0.0, -1.0));
+
+        sim.step();
+        assert!(sim.masses[0].velocity == Point(0.0, -2.0));
+        assert!(sim.masses[0].position == Point(0.0, -3.0));
+    }
 }
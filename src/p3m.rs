@@ -0,0 +1,329 @@
+use super::pm::*;
+use super::*;
+
+/// Builds a particle-particle particle-mesh simulator. The force is split smoothly at `cutoff`:
+/// the long range part is found on a mesh as in the particle-mesh simulator, and the short range
+/// part is summed directly over the neighbors each body finds in a cell list, so close encounters
+/// are as accurate as in direct summation. Bodies wrap round the box but only feel what is inside
+/// it, as the mesh is padded rather than periodic.
+#[derive(Debug)]
+pub struct P3mFactory<L: ForceLaw = Newtonian> {
+    pub law: L,
+    /// Grid points along each side of the box.
+    pub grid_size: usize,
+    /// Side of the box, centered on the origin. Bodies leaving it come back on the other side.
+    pub box_size: Float,
+    pub assignment: Assignment,
+    /// Distance beyond which bodies only feel each other through the mesh. Should span a few grid
+    /// spacings, and be under half the box.
+    pub cutoff: Float,
+}
+
+impl<L: ForceLaw + Default> Default for P3mFactory<L> {
+    fn default() -> Self {
+        P3mFactory {
+            law: L::default(),
+            grid_size: 64,
+            box_size: 400.0,
+            assignment: Assignment::CloudInCell,
+            cutoff: 20.0,
+        }
+    }
+}
+
+impl<L: ForceLaw + Clone + 'static> SimFactory for P3mFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        let law = self.law.clone();
+        let cutoff = self.cutoff;
+        let mesh = Mesh::isolated(self.grid_size, self.box_size, self.assignment, |offset| {
+            law.force(1.0, offset, 1.0, Point::ZERO)
+                * (1.0 - short_range(offset.magnitude() / cutoff))
+        });
+        Box::new(P3mSimulator {
+            masses: masses.into_iter().map(|x| x.wrapped(&mesh)).collect(),
+            mesh,
+            law,
+            fields: ExternalFields::default(),
+            cutoff,
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("P3M Simulator")
+    }
+}
+
+/// Share of the force at `x` cutoffs that is summed directly. Falls from one to zero with zero
+/// slope at both ends, so neither part has a kink for the mesh to smear out.
+fn short_range(x: Float) -> Float {
+    if x >= 1.0 {
+        0.0
+    } else {
+        1.0 - x * x * (3.0 - 2.0 * x)
+    }
+}
+
+/// Bodies binned into square cells at least `cutoff` across, so everything within `cutoff` of a
+/// body lies in its own cell or the eight around it.
+#[derive(Debug)]
+struct CellList {
+    cells: usize,
+    cell_size: Float,
+    box_size: Float,
+    contents: Vec<Vec<usize>>,
+}
+
+impl CellList {
+    fn new(box_size: Float, cutoff: Float, positions: &[Point]) -> CellList {
+        let cells = ((box_size / cutoff).floor() as usize).max(1);
+        let mut list = CellList {
+            cells,
+            cell_size: box_size / cells as Float,
+            box_size,
+            contents: vec![Vec::new(); cells * cells],
+        };
+        for (i, position) in positions.iter().enumerate() {
+            let cell = list.cell(*position);
+            list.contents[cell.1 * cells + cell.0].push(i);
+        }
+        list
+    }
+
+    fn cell(&self, position: Point) -> (usize, usize) {
+        let half = self.box_size / 2.0;
+        let index = |x: Float| (((x + half) / self.cell_size) as usize).min(self.cells - 1);
+        (index(position.0), index(position.1))
+    }
+
+    /// Every body in the cells around `position`, stopping at the edges of the box.
+    fn neighbors(&self, position: Point) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.cell(position);
+        let cells = self.cells as i64;
+        let mut around: Vec<usize> = Vec::with_capacity(9);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (i, j) = (x as i64 + dx, y as i64 + dy);
+                if i >= 0 && j >= 0 && i < cells && j < cells {
+                    around.push((j * cells + i) as usize);
+                }
+            }
+        }
+        around
+            .into_iter()
+            .flat_map(move |cell| self.contents[cell].iter().cloned())
+    }
+}
+
+#[derive(Debug)]
+struct P3mSimulator<L: ForceLaw> {
+    masses: Vec<Mass>,
+    mesh: Mesh,
+    law: L,
+    fields: ExternalFields,
+    cutoff: Float,
+}
+
+impl<L: ForceLaw> P3mSimulator<L> {
+    /// Force per unit coupling on every body.
+    fn field(&self) -> Vec<Point> {
+        let law = &self.law;
+        let positions: Vec<Point> = self.masses.iter().map(|x| x.position).collect();
+        let mut field = self.mesh.field(
+            self.masses
+                .iter()
+                .filter(|x| !x.is_test_particle())
+                .map(|x| (x.position, law.coupling(x))),
+            &positions,
+        );
+
+        let cells = CellList::new(self.mesh.box_size(), self.cutoff, &positions);
+        for (i, position) in positions.iter().enumerate() {
+            for j in cells.neighbors(*position) {
+                let source = &self.masses[j];
+                if j == i || source.is_test_particle() {
+                    continue;
+                }
+                let r = (source.position - *position).magnitude();
+                if r < self.cutoff {
+                    field[i] += law.force(1.0, *position, law.coupling(source), source.position)
+                        * short_range(r / self.cutoff);
+                }
+            }
+        }
+        field
+    }
+}
+
+impl<L: ForceLaw> Simulator for P3mSimulator<L> {
+    fn step(&mut self) {
+        let field = self.field();
+        let law = &self.law;
+        for (x, f) in self.masses.iter_mut().zip(field) {
+            // test particles feel the field as a unit mass would
+            let acceleration = if x.is_test_particle() {
+                f
+            } else {
+                f * (law.coupling(x) / x.mass)
+            };
+            x.velocity += acceleration + self.fields.acceleration(x.position);
+            x.position = self.mesh.wrap(x.position + x.velocity);
+        }
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter())
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut())
+    }
+
+    /// The mesh is padded rather than periodic, so its forces approximate a direct sum.
+    fn acceleration(&self, id: u64) -> Option<Point> {
+        acceleration_of(&self.law, &self.fields, &self.masses, &[], id)
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.masses.push(mass.wrapped(&self.mesh));
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn body(position: Point) -> Mass {
+        Mass {
+            position,
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        }
+    }
+
+    fn simulator(masses: Vec<Mass>) -> Box<dyn Simulator> {
+        P3mFactory {
+            law: Newtonian::default(),
+            grid_size: 64,
+            box_size: 256.0,
+            assignment: Assignment::CloudInCell,
+            cutoff: 16.0,
+        }
+        .with_masses(masses)
+    }
+
+    #[test]
+    fn test_short_range() {
+        assert_eq!(short_range(0.0), 1.0);
+        assert_eq!(short_range(0.5), 0.5);
+        assert_eq!(short_range(1.0), 0.0);
+        assert_eq!(short_range(3.0), 0.0);
+    }
+
+    #[test]
+    fn test_cell_list() {
+        let positions = [
+            Point(0.0, 0.0),
+            Point(15.0, 0.0),
+            Point(50.0, 50.0),
+            Point(-127.0, 0.0),
+            Point(127.0, 0.0),
+        ];
+        let cells = CellList::new(256.0, 16.0, &positions);
+        let found = |p: Point| {
+            let mut found: Vec<usize> = cells.neighbors(p).collect();
+            found.sort_unstable();
+            found
+        };
+
+        assert_eq!(found(positions[0]), vec![0, 1]);
+        assert_eq!(found(positions[2]), vec![2]);
+        // nothing is found across the edge of the box
+        assert_eq!(found(positions[3]), vec![3]);
+
+        // a box only a cell across still lists everyone once
+        let cells = CellList::new(256.0, 300.0, &positions);
+        assert_eq!(cells.neighbors(Point::ZERO).count(), positions.len());
+    }
+
+    #[test]
+    fn test_pair_matches_direct_force() {
+        let law = Newtonian::default();
+        // from close, through the cutoff, to most of the box apart
+        for separation in [1.0, 10.0, 15.0, 30.0, 200.0].iter() {
+            let mut sim = simulator(vec![
+                body(Point(-99.7, 0.2)),
+                body(Point(-99.7 + separation, 0.2)),
+            ]);
+            sim.step();
+
+            let direct = law.force(1.0, Point::ZERO, 1.0, Point(*separation, 0.0));
+            let x = sim.mass_iter().next().unwrap();
+            assert!(
+                (x.velocity - direct).magnitude() < 0.03 * direct.magnitude(),
+                "{}: {:?} vs {:?}",
+                separation,
+                x.velocity,
+                direct
+            );
+        }
+    }
+
+    #[test]
+    fn test_momentum_conserved() {
+        let mut sim = simulator(vec![
+            body(Point(-3.3, 1.0)),
+            body(Point(4.1, -2.7)),
+            Mass {
+                mass: 2.5,
+                ..body(Point(40.5, 7.0))
+            },
+            Mass::new_test_particle(Point(1.0, 1.0), Point::ZERO),
+        ]);
+
+        sim.step();
+
+        let momentum = sim
+            .mass_iter()
+            .fold(Point::ZERO, |p, x| p + x.velocity * x.mass);
+        assert!(momentum.magnitude() < 1e-9);
+        let test = sim.mass_iter().nth(3).unwrap();
+        assert!(test.velocity.magnitude() > 0.0);
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let mut masses = vec![
+            body(Point(-3.3, 1.0)),
+            body(Point(40.5, 7.0)),
+            Mass::new_test_particle(Point(1.0, 1.0), Point::ZERO),
+        ];
+        for (i, x) in masses.iter_mut().enumerate() {
+            x.id = i as u64 + 1;
+        }
+        let mut sim = simulator(masses);
+        let expected: Vec<Point> = (1..=3).map(|id| sim.acceleration(id).unwrap()).collect();
+        assert!(sim.acceleration(4).is_none());
+
+        // from rest, one step's change in velocity is the acceleration, as well as the mesh can
+        sim.step();
+        for (id, a) in (1..=3).zip(expected) {
+            let v = sim.find_mass(id).unwrap().velocity;
+            assert!(
+                (v - a).magnitude() < 0.1 * a.magnitude(),
+                "{:?} vs {:?}",
+                v,
+                a
+            );
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use super::*;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::sync::Arc;
+
+/// How a body's coupling is spread over the grid points around it, and how the field at those
+/// points is gathered back.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Assignment {
+    /// All of it on the closest grid point.
+    NearestGridPoint,
+    /// Shared linearly between the four surrounding grid points.
+    CloudInCell,
+    /// Shared quadratically between the nine closest grid points.
+    TriangularShapedCloud,
+}
+
+impl Assignment {
+    /// Grid points along one axis, and their weights, for the grid coordinate `u`.
+    fn weights(self, u: Float) -> Vec<(i64, Float)> {
+        match self {
+            Assignment::NearestGridPoint => vec![(u.round() as i64, 1.0)],
+            Assignment::CloudInCell => {
+                let i = u.floor();
+                let f = u - i;
+                vec![(i as i64, 1.0 - f), (i as i64 + 1, f)]
+            }
+            Assignment::TriangularShapedCloud => {
+                let i = u.round();
+                let d = u - i;
+                vec![
+                    (i as i64 - 1, 0.5 * (0.5 - d) * (0.5 - d)),
+                    (i as i64, 0.75 - d * d),
+                    (i as i64 + 1, 0.5 * (0.5 + d) * (0.5 + d)),
+                ]
+            }
+        }
+    }
+}
+
+/// A square grid of `size` points on a side covering `box_size`, centered on the origin, on which
+/// couplings are spread and their field found with the FFT. A periodic mesh solves Poisson's
+/// equation with the box repeating forever; an isolated one convolves with a force kernel over a
+/// grid padded to twice the size, so nothing pulls across the edge of the box.
+pub struct Mesh {
+    size: usize,
+    /// Points along a side of the grid the FFT works on: `size`, or twice that when padded.
+    grid: usize,
+    box_size: Float,
+    assignment: Assignment,
+    kernel_x: Vec<Complex<Float>>,
+    kernel_y: Vec<Complex<Float>>,
+    forward: Arc<dyn Fft<Float>>,
+    inverse: Arc<dyn Fft<Float>>,
+}
+
+impl Debug for Mesh {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("Mesh")
+            .field("size", &self.size)
+            .field("grid", &self.grid)
+            .field("box_size", &self.box_size)
+            .field("assignment", &self.assignment)
+            .finish()
+    }
+}
+
+impl Mesh {
+    fn empty(size: usize, grid: usize, box_size: Float, assignment: Assignment) -> Mesh {
+        let mut planner = FftPlanner::new();
+        Mesh {
+            size,
+            grid,
+            box_size,
+            assignment,
+            kernel_x: vec![Complex::new(0.0, 0.0); grid * grid],
+            kernel_y: vec![Complex::new(0.0, 0.0); grid * grid],
+            forward: planner.plan_fft_forward(grid),
+            inverse: planner.plan_fft_inverse(grid),
+        }
+    }
+
+    /// Solves ∇²φ = `source` ρ with periodic boundaries and takes the force as -∇φ, both with
+    /// finite differences on the grid so a body sitting on a grid point pulls no differently from
+    /// one between them; see `ForceLaw::poisson_source`. As in any periodic box the mean density
+    /// is taken away, so a lone body feels nothing.
+    pub fn periodic(size: usize, box_size: Float, assignment: Assignment, source: Float) -> Mesh {
+        let mut mesh = Mesh::empty(size, size, box_size, assignment);
+        let spacing = mesh.spacing();
+        // the phase a wave steps through from one grid point to the next
+        let phase = |m: usize| 2.0 * std::f64::consts::PI * m as Float / size as Float;
+        for j in 0..size {
+            for i in 0..size {
+                let (px, py) = (phase(i), phase(j));
+                // the five point Laplacian of a wave, as a multiple of the wave
+                let laplacian = -4.0 * ((px / 2.0).sin().powi(2) + (py / 2.0).sin().powi(2))
+                    / (spacing * spacing);
+                if laplacian == 0.0 {
+                    continue;
+                }
+                // couplings spread over a cell become a density through the cell's area
+                let potential = source / (laplacian * spacing * spacing);
+                // minus the central difference of the potential
+                mesh.kernel_x[j * size + i] = Complex::new(0.0, -px.sin() / spacing * potential);
+                mesh.kernel_y[j * size + i] = Complex::new(0.0, -py.sin() / spacing * potential);
+            }
+        }
+        mesh
+    }
+
+    /// Convolves with `kernel`, the force on a unit coupling at an offset from a unit coupling,
+    /// with no periodic images: the grid is padded with zeros to twice the size, so the FFT's
+    /// wrapping never brings two points of the box closer than they are.
+    pub fn isolated<K: Fn(Point) -> Point>(
+        size: usize,
+        box_size: Float,
+        assignment: Assignment,
+        kernel: K,
+    ) -> Mesh {
+        let grid = 2 * size;
+        let mut mesh = Mesh::empty(size, grid, box_size, assignment);
+        let spacing = mesh.spacing();
+        for j in 0..grid {
+            for i in 0..grid {
+                let (di, dj) = (wrapped(i as i64, grid), wrapped(j as i64, grid));
+                // points of the box are never a whole box apart
+                if di.abs() >= size as i64 || dj.abs() >= size as i64 {
+                    continue;
+                }
+                let force = kernel(Point(di as Float, dj as Float) * spacing);
+                mesh.kernel_x[j * grid + i] = Complex::new(force.0, 0.0);
+                mesh.kernel_y[j * grid + i] = Complex::new(force.1, 0.0);
+            }
+        }
+        let (mut kernel_x, mut kernel_y) = (mesh.kernel_x.clone(), mesh.kernel_y.clone());
+        mesh.fft(&mut kernel_x, false);
+        mesh.fft(&mut kernel_y, false);
+        mesh.kernel_x = kernel_x;
+        mesh.kernel_y = kernel_y;
+        mesh
+    }
+
+    pub fn box_size(&self) -> Float {
+        self.box_size
+    }
+
+    pub fn spacing(&self) -> Float {
+        self.box_size / self.size as Float
+    }
+
+    /// The periodic image of `position` inside the box.
+    pub fn wrap(&self, position: Point) -> Point {
+        let half = self.box_size / 2.0;
+        let wrap = |x: Float| (x + half).rem_euclid(self.box_size) - half;
+        Point(wrap(position.0), wrap(position.1))
+    }
+
+    /// Grid cells around `position`, and the share of the body each one gets.
+    fn cells(&self, position: Point) -> Vec<(usize, Float)> {
+        let half = self.box_size / 2.0;
+        let xs = self
+            .assignment
+            .weights((position.0 + half) / self.spacing());
+        let ys = self
+            .assignment
+            .weights((position.1 + half) / self.spacing());
+        let mut cells = Vec::with_capacity(xs.len() * ys.len());
+        for (j, wy) in ys.iter() {
+            for (i, wx) in xs.iter() {
+                let (i, j) = (
+                    i.rem_euclid(self.grid as i64) as usize,
+                    j.rem_euclid(self.grid as i64) as usize,
+                );
+                cells.push((j * self.grid + i, wx * wy));
+            }
+        }
+        cells
+    }
+
+    /// Force per unit coupling at each of `positions` from `sources` of a given coupling.
+    pub fn field<I: Iterator<Item = (Point, Float)>>(
+        &self,
+        sources: I,
+        positions: &[Point],
+    ) -> Vec<Point> {
+        let mut density = vec![Complex::new(0.0, 0.0); self.grid * self.grid];
+        for (position, coupling) in sources {
+            for (cell, weight) in self.cells(position) {
+                density[cell].re += coupling * weight;
+            }
+        }
+        self.fft(&mut density, false);
+
+        let mut field_x: Vec<Complex<Float>> = density
+            .iter()
+            .zip(self.kernel_x.iter())
+            .map(|(d, k)| d * k)
+            .collect();
+        let mut field_y: Vec<Complex<Float>> = density
+            .iter()
+            .zip(self.kernel_y.iter())
+            .map(|(d, k)| d * k)
+            .collect();
+        self.fft(&mut field_x, true);
+        self.fft(&mut field_y, true);
+
+        let scale = 1.0 / (self.grid * self.grid) as Float;
+        positions
+            .iter()
+            .map(|position| {
+                self.cells(*position)
+                    .into_iter()
+                    .fold(Point::ZERO, |f, (cell, weight)| {
+                        f + Point(field_x[cell].re, field_y[cell].re) * (weight * scale)
+                    })
+            })
+            .collect()
+    }
+
+    /// Two dimensional FFT of a row-major grid, one axis at a time.
+    fn fft(&self, data: &mut [Complex<Float>], inverse: bool) {
+        let fft = if inverse {
+            &self.inverse
+        } else {
+            &self.forward
+        };
+        fft.process(data);
+
+        let mut column = vec![Complex::new(0.0, 0.0); self.grid];
+        for i in 0..self.grid {
+            for j in 0..self.grid {
+                column[j] = data[j * self.grid + i];
+            }
+            fft.process(&mut column);
+            for j in 0..self.grid {
+                data[j * self.grid + i] = column[j];
+            }
+        }
+    }
+}
+
+/// The offset of grid index `i` from index zero, going the short way round.
+fn wrapped(i: i64, size: usize) -> i64 {
+    let size = size as i64;
+    if i > size / 2 {
+        i - size
+    } else {
+        i
+    }
+}
+
+/// Builds a particle-mesh simulator: couplings are assigned to a grid and their forces found with
+/// the FFT, at a cost of O(N + G² log G) for a grid of G² points. Bodies wrap round the box. For
+/// gravity in the plane, `Logarithmic`, the mesh solves Poisson's equation and the box repeats
+/// forever; any other law is convolved over a padded grid, so bodies only feel what is inside
+/// the box.
+#[derive(Debug)]
+pub struct PmFactory<L: ForceLaw = Logarithmic> {
+    pub law: L,
+    /// Grid points along each side of the box.
+    pub grid_size: usize,
+    /// Side of the periodic box, centered on the origin.
+    pub box_size: Float,
+    pub assignment: Assignment,
+}
+
+impl<L: ForceLaw + Default> Default for PmFactory<L> {
+    fn default() -> Self {
+        PmFactory {
+            law: L::default(),
+            grid_size: 64,
+            box_size: 400.0,
+            assignment: Assignment::CloudInCell,
+        }
+    }
+}
+
+impl<L: ForceLaw + Clone + 'static> SimFactory for PmFactory<L> {
+    fn with_masses(&self, masses: Vec<Mass>) -> Box<dyn Simulator> {
+        let law = self.law.clone();
+        let mesh = match law.poisson_source() {
+            Some(source) => Mesh::periodic(self.grid_size, self.box_size, self.assignment, source),
+            None => Mesh::isolated(self.grid_size, self.box_size, self.assignment, |offset| {
+                law.force(1.0, offset, 1.0, Point::ZERO)
+            }),
+        };
+        Box::new(PmSimulator {
+            masses: masses.into_iter().map(|m| m.wrapped(&mesh)).collect(),
+            mesh,
+            law,
+            fields: ExternalFields::default(),
+        })
+    }
+
+    fn name(&self) -> String {
+        String::from("Particle Mesh Simulator")
+    }
+}
+
+impl Mass {
+    /// The same body moved to its periodic image inside `mesh`'s box.
+    pub fn wrapped(mut self, mesh: &Mesh) -> Mass {
+        self.position = mesh.wrap(self.position);
+        self
+    }
+}
+
+#[derive(Debug)]
+struct PmSimulator<L: ForceLaw> {
+    masses: Vec<Mass>,
+    mesh: Mesh,
+    law: L,
+    fields: ExternalFields,
+}
+
+impl<L: ForceLaw> Simulator for PmSimulator<L> {
+    fn step(&mut self) {
+        let law = &self.law;
+        let positions: Vec<Point> = self.masses.iter().map(|x| x.position).collect();
+        let field = self.mesh.field(
+            self.masses.iter().map(|x| (x.position, law.coupling(x))),
+            &positions,
+        );
+
+        for (x, f) in self.masses.iter_mut().zip(field) {
+            // test particles feel the field as a unit mass would
+            let acceleration = if x.is_test_particle() {
+                f
+            } else {
+                f * (law.coupling(x) / x.mass)
+            };
+            x.velocity += acceleration + self.fields.acceleration(x.position);
+            x.position = self.mesh.wrap(x.position + x.velocity);
+        }
+    }
+
+    fn add_field(&mut self, field: Box<dyn ExternalField>) {
+        self.fields.push(field);
+    }
+
+    fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+        Box::new(self.masses.iter())
+    }
+
+    fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+        Box::new(self.masses.iter_mut())
+    }
+
+    fn add_mass(&mut self, mass: Mass) {
+        self.masses.push(mass.wrapped(&self.mesh));
+    }
+
+    fn remove_mass(&mut self, id: u64) -> Option<Mass> {
+        remove_by_id(&mut self.masses, id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn body(position: Point) -> Mass {
+        Mass {
+            position,
+            velocity: Point::ZERO,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        }
+    }
+
+    const TWO_PI: Float = 2.0 * std::f64::consts::PI;
+
+    fn mesh(assignment: Assignment) -> Mesh {
+        Mesh::periodic(64, 64.0, assignment, TWO_PI)
+    }
+
+    /// Force on a unit coupling at `offset` from a unit coupling under `Logarithmic` gravity in
+    /// a periodic box, summed straight from the Fourier series of the field.
+    fn periodic_force(offset: Point, box_size: Float) -> Point {
+        let terms = 256;
+        let mut force = Point::ZERO;
+        for n in -terms..=terms {
+            for m in -terms..=terms {
+                let k = Point(m as Float, n as Float) * (TWO_PI / box_size);
+                let k2 = k.magnitude_squared();
+                if k2 > 0.0 {
+                    force += k * ((k.0 * offset.0 + k.1 * offset.1).sin() / k2);
+                }
+            }
+        }
+        force * (-TWO_PI / (box_size * box_size))
+    }
+
+    #[test]
+    fn test_weights() {
+        for assignment in [
+            Assignment::NearestGridPoint,
+            Assignment::CloudInCell,
+            Assignment::TriangularShapedCloud,
+        ]
+        .iter()
+        {
+            for u in [0.0, 0.25, 0.5, 3.9].iter() {
+                let total: Float = assignment.weights(*u).iter().map(|(_, w)| w).sum();
+                assert!((total - 1.0).abs() < Point::EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mesh = mesh(Assignment::CloudInCell);
+        assert_eq!(mesh.wrap(Point(1.0, -2.0)), Point(1.0, -2.0));
+        assert_eq!(mesh.wrap(Point(33.0, -33.0)), Point(-31.0, 31.0));
+        assert_eq!(mesh.wrap(Point(100.0, 0.0)), Point(-28.0, 0.0));
+    }
+
+    #[test]
+    fn test_matches_periodic_field() {
+        // nearest grid point moves bodies onto the grid, so is the least accurate
+        for (assignment, tolerance) in [
+            (Assignment::NearestGridPoint, 0.1),
+            (Assignment::CloudInCell, 0.02),
+            (Assignment::TriangularShapedCloud, 0.03),
+        ]
+        .iter()
+        {
+            let mesh = mesh(*assignment);
+            // close, and a large part of the box apart
+            for (a, b) in [
+                (Point(-5.0, 0.3), Point(5.0, -0.3)),
+                (Point(-12.2, -6.1), Point(12.3, 5.8)),
+            ]
+            .iter()
+            {
+                let field = mesh.field(vec![(*b, 2.0)].into_iter(), &[*a]);
+                let expected = periodic_force(*a - *b, 64.0) * 2.0;
+                assert!(
+                    (field[0] - expected).magnitude() < tolerance * expected.magnitude(),
+                    "{:?}: {:?} vs {:?}",
+                    assignment,
+                    field[0],
+                    expected
+                );
+            }
+        }
+
+        // halfway round the box the images pull equally both ways
+        let mesh = mesh(Assignment::CloudInCell);
+        let field = mesh.field(
+            vec![(Point(16.0, 0.0), 1.0)].into_iter(),
+            &[Point(-16.0, 0.0)],
+        );
+        assert!(field[0].magnitude() < 1e-9, "{:?}", field[0]);
+    }
+
+    #[test]
+    fn test_isolated_matches_direct_force() {
+        let law = Newtonian::default();
+        let mesh = Mesh::isolated(64, 64.0, Assignment::CloudInCell, |offset| {
+            law.force(1.0, offset, 1.0, Point::ZERO)
+        });
+        // most of the box apart, where a periodic mesh would see the images far closer
+        let (a, b) = (Point(-25.3, -1.2), Point(25.7, 1.1));
+        let field = mesh.field(vec![(b, 2.0)].into_iter(), &[a]);
+        let direct = law.force(1.0, a, 2.0, b);
+        assert!(
+            (field[0] - direct).magnitude() < 0.02 * direct.magnitude(),
+            "{:?} vs {:?}",
+            field[0],
+            direct
+        );
+    }
+
+    #[test]
+    fn test_momentum_conserved() {
+        let mut sim = PmFactory::<Logarithmic> {
+            box_size: 200.0,
+            ..PmFactory::default()
+        }
+        .with_masses(vec![
+            body(Point(-3.3, 1.0)),
+            body(Point(4.1, -2.7)),
+            Mass {
+                mass: 2.5,
+                ..body(Point(0.5, 7.0))
+            },
+        ]);
+
+        sim.step();
+
+        let momentum = sim
+            .mass_iter()
+            .fold(Point::ZERO, |p, x| p + x.velocity * x.mass);
+        assert!(momentum.magnitude() < 1e-9);
+        let masses: Vec<&Mass> = sim.mass_iter().collect();
+        assert!(masses[0].velocity.0 > 0.0);
+        assert!(masses[1].velocity.0 < 0.0);
+    }
+
+    #[test]
+    fn test_periodic() {
+        let mut sim = PmFactory::<Logarithmic>::default().with_masses(vec![Mass {
+            velocity: Point(150.0, 0.0),
+            ..body(Point(100.0, 0.0))
+        }]);
+
+        sim.step();
+
+        // a lone body feels no force, and comes back round the other side of the box
+        let x = sim.mass_iter().next().unwrap();
+        assert!((x.velocity - Point(150.0, 0.0)).magnitude() < Point::EPSILON);
+        assert!((x.position - Point(-150.0, 0.0)).magnitude() < Point::EPSILON);
+    }
+}
@@ -14,6 +14,7 @@ impl Point {
         Point(self.0 * s, self.1 * s)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn add(self, that: Point) -> Point {
         Point(self.0 + that.0, self.1 + that.1)
     }
@@ -26,6 +27,10 @@ impl Point {
         self.scale(-1.0)
     }
 
+    pub fn dot(self, that: Point) -> Float {
+        self.0 * that.0 + self.1 * that.1
+    }
+
     pub fn magnitude_squared(self) -> Float {
         self.0 * self.0 + self.1 * self.1
     }
@@ -39,7 +44,11 @@ impl Point {
     }
 
     pub fn new_random() -> Point {
-        let mut rng = rand::thread_rng();
+        Point::new_random_from(&mut rand::thread_rng())
+    }
+
+    /// A random point in the unit square about the origin, drawn from `rng`.
+    pub fn new_random_from<R: Rng>(rng: &mut R) -> Point {
         Point(rng.gen::<f64>() - 0.5, rng.gen::<f64>() - 0.5)
     }
 }
@@ -101,6 +110,7 @@ impl Div<Float> for Point {
 }
 
 #[cfg(test)]
+#[allow(clippy::neg_cmp_op_on_partial_ord)]
 mod test {
     use super::*;
 
@@ -127,6 +137,12 @@ mod test {
         assert!(Point(1.0, 2.0).inverse() == Point(-1.0, -2.0));
     }
 
+    #[test]
+    fn test_dot() {
+        assert!(Point(1.0, 2.0).dot(Point(3.0, -4.0)) == -5.0);
+        assert!(Point(1.0, 0.0).dot(Point(0.0, 1.0)) == 0.0);
+    }
+
     #[test]
     fn test_magnitude_squared() {
         assert!(Point(3.0, 4.0).magnitude_squared() == 25.0);
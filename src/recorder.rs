@@ -0,0 +1,278 @@
+use super::*;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// How a `Recorder` lays out its records.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// Comma separated values under a `step,time,id,x,y,vx,vy,mass` header.
+    Csv,
+    /// One JSON object per line, with the same fields as the CSV columns.
+    Ndjson,
+    /// Fixed 64 byte little-endian records with no header: the step and id as `u64`, and the
+    /// time, x, y, vx, vy and mass as `f64`, in the same order as the CSV columns. In numpy that
+    /// is `dtype=[('step','<u8'),('time','<f8'),('id','<u8'),('x','<f8'),('y','<f8'),
+    /// ('vx','<f8'),('vy','<f8'),('mass','<f8')]`.
+    Binary,
+}
+
+impl Format {
+    /// The format a file name's extension calls for, if it is one `--format` would take.
+    pub fn from_path(path: &str) -> Option<Format> {
+        path.rsplit('.').next()?.parse().ok()
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Format, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "ndjson" | "jsonl" | "json" => Ok(Format::Ndjson),
+            "binary" | "bin" => Ok(Format::Binary),
+            _ => Err(format!(
+                "unknown format {}, expected csv, ndjson or binary",
+                s
+            )),
+        }
+    }
+}
+
+/// Picks the bodies a `Recorder` writes.
+pub type Filter = Box<dyn Fn(&Mass) -> bool>;
+
+/// Writes the state of a simulator after every step it is shown, one record per body.
+pub struct Recorder<W: Write> {
+    out: W,
+    pub format: Format,
+    /// Only every `every`th step is written, starting with the first.
+    pub every: usize,
+    /// Bodies to write; all of them when there is no filter.
+    pub filter: Option<Filter>,
+    observed: usize,
+    time: Float,
+}
+
+impl<W: Write> Debug for Recorder<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("Recorder")
+            .field("format", &self.format)
+            .field("every", &self.every)
+            .field("filtered", &self.filter.is_some())
+            .field("observed", &self.observed)
+            .field("time", &self.time)
+            .finish()
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(out: W, format: Format) -> Recorder<W> {
+        Recorder {
+            out,
+            format,
+            every: 1,
+            filter: None,
+            observed: 0,
+            time: 0.0,
+        }
+    }
+
+    /// Writes the initial state, then steps `sim` `steps` times writing each new state.
+    pub fn run(&mut self, sim: &mut dyn Simulator, steps: usize) -> io::Result<()> {
+        self.observe(sim)?;
+        for _i in 0..steps {
+            sim.step();
+            self.observe(sim)?;
+        }
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Records `sim` as it is now. The first call sees the initial state at time zero, and each
+    /// later one the state a step after the call before.
+    pub fn observe(&mut self, sim: &dyn Simulator) -> io::Result<()> {
+        if self.observed > 0 {
+            self.time += sim.time_step();
+        }
+        let step = self.observed;
+        self.observed += 1;
+        if step % self.every.max(1) != 0 {
+            return Ok(());
+        }
+
+        if step == 0 && self.format == Format::Csv {
+            writeln!(self.out, "step,time,id,x,y,vx,vy,mass")?;
+        }
+        for x in sim.mass_iter() {
+            if self.filter.as_ref().map_or(true, |keep| keep(x)) {
+                self.write(step, x)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, step: usize, x: &Mass) -> io::Result<()> {
+        let time = self.time;
+        match self.format {
+            Format::Csv => writeln!(
+                self.out,
+                "{},{},{},{},{},{},{},{}",
+                step,
+                time,
+                x.id,
+                x.position.0,
+                x.position.1,
+                x.velocity.0,
+                x.velocity.1,
+                x.mass
+            ),
+            Format::Ndjson => writeln!(
+                self.out,
+                "{{\"step\":{},\"time\":{},\"id\":{},\"x\":{},\"y\":{},\"vx\":{},\"vy\":{},\"mass\":{}}}",
+                step,
+                json(time),
+                x.id,
+                json(x.position.0),
+                json(x.position.1),
+                json(x.velocity.0),
+                json(x.velocity.1),
+                json(x.mass)
+            ),
+            Format::Binary => {
+                self.out.write_all(&(step as u64).to_le_bytes())?;
+                self.out.write_all(&time.to_le_bytes())?;
+                self.out.write_all(&x.id.to_le_bytes())?;
+                for value in [
+                    x.position.0,
+                    x.position.1,
+                    x.velocity.0,
+                    x.velocity.1,
+                    x.mass,
+                ]
+                .iter()
+                {
+                    self.out.write_all(&value.to_le_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A number as JSON has it, which has no room for infinities or NaN.
+fn json(x: Float) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        String::from("null")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+    use std::convert::TryInto;
+
+    fn simulator() -> Box<dyn Simulator> {
+        NoGravityFactory {}.with_masses(vec![
+            Mass {
+                position: Point(0.0, 1.0),
+                velocity: Point(1.0, 0.0),
+                mass: 2.0,
+                charge: 0.0,
+                id: 7,
+            },
+            Mass {
+                id: 8,
+                ..Mass::new_test_particle(Point::ZERO, Point(0.0, -0.5))
+            },
+        ])
+    }
+
+    fn record(format: Format, every: usize, steps: usize) -> Vec<u8> {
+        let mut recorder = Recorder::new(Vec::new(), format);
+        recorder.every = every;
+        recorder.run(&mut *simulator(), steps).unwrap();
+        recorder.into_inner()
+    }
+
+    #[test]
+    fn test_csv() {
+        let text = String::from_utf8(record(Format::Csv, 1, 1)).unwrap();
+        assert_eq!(
+            text,
+            "step,time,id,x,y,vx,vy,mass\n\
+             0,0,7,0,1,1,0,2\n\
+             0,0,8,0,0,0,-0.5,0\n\
+             1,1,7,1,1,1,0,2\n\
+             1,1,8,0,-0.5,0,-0.5,0\n"
+        );
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let text = String::from_utf8(record(Format::Ndjson, 1, 0)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "{\"step\":0,\"time\":0,\"id\":7,\"x\":0,\"y\":1,\"vx\":1,\"vy\":0,\"mass\":2}"
+        );
+        assert_eq!(json(Float::NAN), "null");
+    }
+
+    #[test]
+    fn test_binary() {
+        let bytes = record(Format::Binary, 1, 2);
+        assert_eq!(bytes.len(), 3 * 2 * 64);
+
+        // the last record is the test particle two steps on
+        let last = &bytes[5 * 64..];
+        let u64_at = |i: usize| u64::from_le_bytes(last[i..i + 8].try_into().unwrap());
+        let f64_at = |i: usize| f64::from_le_bytes(last[i..i + 8].try_into().unwrap());
+        assert_eq!(u64_at(0), 2);
+        assert_eq!(f64_at(8), 2.0);
+        assert_eq!(u64_at(16), 8);
+        assert_eq!(f64_at(32), -1.0);
+        assert_eq!(f64_at(48), -0.5);
+    }
+
+    #[test]
+    fn test_decimation_and_filter() {
+        let mut recorder = Recorder::new(Vec::new(), Format::Csv);
+        recorder.every = 3;
+        recorder.filter = Some(Box::new(|x: &Mass| x.id == 7));
+        recorder.run(&mut *simulator(), 7).unwrap();
+
+        let text = String::from_utf8(recorder.into_inner()).unwrap();
+        let steps: Vec<&str> = text
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(steps, vec!["0", "3", "6"]);
+    }
+
+    #[test]
+    fn test_format_names() {
+        assert_eq!(Format::from_path("out/run.CSV"), Some(Format::Csv));
+        assert_eq!(Format::from_path("run.jsonl"), Some(Format::Ndjson));
+        assert_eq!(Format::from_path("run.bin"), Some(Format::Binary));
+        assert_eq!(Format::from_path("run"), None);
+        assert_eq!("ndjson".parse::<Format>(), Ok(Format::Ndjson));
+        // an extension and a --format value mean the same thing
+        assert_eq!(Format::from_path("run.json"), Some(Format::Ndjson));
+        assert_eq!("json".parse::<Format>(), Ok(Format::Ndjson));
+        assert_eq!("binary".parse::<Format>(), Ok(Format::Binary));
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
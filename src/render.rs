@@ -0,0 +1,395 @@
+use super::view::*;
+use super::*;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A picture in 8 bit RGB, row by row from the top left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, background: [u8; 3]) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Paints the pixels under a rectangle, mixing `alpha` of `color` into what is there. Parts
+    /// off the picture are dropped.
+    pub fn fill_rect(
+        &mut self,
+        x: Float,
+        y: Float,
+        w: Float,
+        h: Float,
+        color: [u8; 3],
+        alpha: Float,
+    ) {
+        let clip = |v: Float, limit: usize| v.max(0.0).min(limit as Float) as usize;
+        let (left, right) = (
+            clip(x.floor(), self.width),
+            clip((x + w).ceil(), self.width),
+        );
+        let (top, bottom) = (
+            clip(y.floor(), self.height),
+            clip((y + h).ceil(), self.height),
+        );
+        for row in top..bottom {
+            for pixel in self.pixels[row * self.width + left..row * self.width + right].iter_mut() {
+                for (c, new) in pixel.iter_mut().zip(color.iter()) {
+                    *c = (*c as Float * (1.0 - alpha) + *new as Float * alpha).round() as u8;
+                }
+            }
+        }
+    }
+
+    /// Writes a binary PPM, which ffmpeg and most image tools read directly.
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in self.pixels.iter() {
+            out.write_all(pixel)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a PNG. The image data is stored without compression, which keeps the encoder small
+    /// at the cost of files about as large as a PPM.
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bit RGB, deflate, no filtering, not interlaced
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        // every row starts with its filter type, none
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            raw.push(0);
+            for pixel in row.iter() {
+                raw.extend_from_slice(pixel);
+            }
+        }
+        write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+        write_chunk(out, b"IEND", &[])
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W, format: ImageFormat) -> io::Result<()> {
+        match format {
+            ImageFormat::Png => self.write_png(out),
+            ImageFormat::Ppm => self.write_ppm(out),
+        }
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()).cloned());
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// The CRC-32 PNG checks its chunks with.
+fn crc32<I: Iterator<Item = u8>>(bytes: I) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= byte as u32;
+        for _bit in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// A zlib stream holding `data` in stored, uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 11);
+    out.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ImageFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "ppm" => Ok(ImageFormat::Ppm),
+            _ => Err(format!("unknown image format {}, expected png or ppm", s)),
+        }
+    }
+}
+
+/// Draws simulators the way the GTK viewer does, without needing a display: bodies are squares
+/// sized by mass and colored as `color_by` asks.
+#[derive(Debug)]
+pub struct Renderer {
+    pub width: usize,
+    pub height: usize,
+    pub background: [u8; 3],
+    pub color_by: ColorBy,
+    /// Number of past positions drawn behind each body, fading with age; none when zero.
+    pub trails: usize,
+    pub scale: AutoScale,
+    history: Trails,
+}
+
+impl Renderer {
+    pub fn new(width: usize, height: usize) -> Renderer {
+        Renderer {
+            width,
+            height,
+            background: [0, 0, 0],
+            color_by: ColorBy::Auto,
+            trails: 0,
+            scale: AutoScale::default(),
+            history: Trails::default(),
+        }
+    }
+
+    /// Draws the bodies of `sim` as they are now, remembering their positions for the trails.
+    pub fn render(&mut self, sim: &dyn Simulator) -> Image {
+        let (width, height) = (self.width as Float, self.height as Float);
+        let mut image = Image::new(self.width, self.height, self.background);
+        let masses: Vec<&Mass> = sim.mass_iter().collect();
+        self.scale.update(masses.iter().cloned());
+        let color_by = self.color_by.pick(masses.iter().cloned());
+
+        for m in masses.iter() {
+            let color = to_bytes(body_color(m, color_by, &self.scale));
+            for (position, alpha) in self.history.trail(m.id) {
+                let p = self.scale.to_screen(position, width, height);
+                image.fill_rect(p.0, p.1, 1.0, 1.0, color, alpha);
+            }
+        }
+        self.history.length = self.trails;
+        self.history.record(&masses);
+
+        for m in masses.iter() {
+            let p = self.scale.to_screen(m.position, width, height);
+            let size = body_size(m);
+            let color = to_bytes(body_color(m, color_by, &self.scale));
+            image.fill_rect(p.0, p.1, size, size, color, 1.0);
+        }
+        image
+    }
+}
+
+/// Renders a simulator to numbered image files, ready for `ffmpeg -i name_%06d.png`. Frames are
+/// numbered from zero without gaps, however many steps lie between them.
+#[derive(Debug)]
+pub struct FrameSeries {
+    dir: PathBuf,
+    name: String,
+    format: ImageFormat,
+    pub renderer: Renderer,
+    /// Only every `every`th step is drawn, starting with the first.
+    pub every: usize,
+    observed: usize,
+    frames: usize,
+}
+
+impl FrameSeries {
+    /// A series of frames named after `name` in `dir`, which is made if it isn't there.
+    pub fn new<P: Into<PathBuf>>(
+        dir: P,
+        name: &str,
+        format: ImageFormat,
+        renderer: Renderer,
+    ) -> io::Result<FrameSeries> {
+        let dir = dir.into();
+        create_dir_all(&dir)?;
+        Ok(FrameSeries {
+            dir,
+            name: name.to_string(),
+            format,
+            renderer,
+            every: 1,
+            observed: 0,
+            frames: 0,
+        })
+    }
+
+    /// Draws the initial state, then steps `sim` `steps` times drawing each new state.
+    pub fn run(&mut self, sim: &mut dyn Simulator, steps: usize) -> io::Result<()> {
+        self.observe(sim)?;
+        for _i in 0..steps {
+            sim.step();
+            self.observe(sim)?;
+        }
+        Ok(())
+    }
+
+    /// Draws `sim` as it is now if this step is one to keep. Trails are only as long as the
+    /// frames that were drawn.
+    pub fn observe(&mut self, sim: &dyn Simulator) -> io::Result<()> {
+        let step = self.observed;
+        self.observed += 1;
+        if step % self.every.max(1) != 0 {
+            return Ok(());
+        }
+
+        let image = self.renderer.render(sim);
+        let file = format!(
+            "{}_{:06}.{}",
+            self.name,
+            self.frames,
+            self.format.extension()
+        );
+        let mut out = BufWriter::new(File::create(self.dir.join(file))?);
+        image.write(&mut out, self.format)?;
+        self.frames += 1;
+        out.flush()
+    }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+
+    fn body(position: Point, velocity: Point, mass: Float) -> Mass {
+        Mass {
+            position,
+            velocity,
+            mass,
+            charge: 0.0,
+            id: Mass::new_id(),
+        }
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789".iter().cloned()), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND".iter().cloned()), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_zlib_blocks() {
+        let data = vec![7u8; 70000];
+        let stream = zlib_stored(&data);
+        assert_eq!(stream.len(), 2 + 5 + 65535 + 5 + (70000 - 65535) + 4);
+        // the first block isn't final, the second is
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + 5 + 65535], 1);
+        assert_eq!(&stream[3..5], &[0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_png_layout() {
+        let mut image = Image::new(3, 2, [10, 20, 30]);
+        image.fill_rect(1.0, 1.0, 1.0, 1.0, [255, 0, 0], 1.0);
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // the second row holds the red pixel after its filter byte
+        let raw = &png[8 + 25 + 8 + 2 + 5..];
+        assert_eq!(&raw[10..20], &[0, 10, 20, 30, 255, 0, 0, 10, 20, 30]);
+    }
+
+    #[test]
+    fn test_ppm() {
+        let image = Image::new(2, 1, [1, 2, 3]);
+        let mut ppm = Vec::new();
+        image.write_ppm(&mut ppm).unwrap();
+        assert_eq!(ppm, b"P6\n2 1\n255\n\x01\x02\x03\x01\x02\x03");
+    }
+
+    #[test]
+    fn test_fill_rect_clips_and_blends() {
+        let mut image = Image::new(4, 4, [0, 0, 0]);
+        image.fill_rect(-2.0, 2.5, 3.0, 10.0, [200, 100, 0], 0.5);
+        assert_eq!(image.pixel(0, 3), [100, 50, 0]);
+        assert_eq!(image.pixel(1, 2), [0, 0, 0]);
+        assert_eq!(image.pixel(0, 1), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_render() {
+        let fast = body(Point(10.0, 0.0), Point(0.0, 2.0), 0.5);
+        let slow = body(Point(-10.0, 0.0), Point::ZERO, 0.5);
+        let mut sim = NoGravityFactory {}.with_masses(vec![fast, slow]);
+        let mut renderer = Renderer::new(100, 100);
+        renderer.trails = 3;
+
+        let image = renderer.render(&*sim);
+        // the bodies sit at the edges of the fitted picture, fast in green and slow in red
+        assert_eq!(image.pixel(97, 50), [0, 255, 0]);
+        assert_eq!(image.pixel(2, 50), [255, 0, 0]);
+
+        sim.step();
+        let image = renderer.render(&*sim);
+        let trail = image.pixel(97, 50);
+        assert!(trail[1] > 0 && trail[1] < 255, "{:?}", trail);
+    }
+}
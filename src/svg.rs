@@ -0,0 +1,206 @@
+use super::view::*;
+use super::*;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Collects what a simulator does and draws it as an SVG: each body as a circle sized by its mass
+/// where it ended up and, when `paths` is set, the way it came as a line. The picture is fitted to
+/// everything seen, so the whole of every path is in view.
+#[derive(Debug)]
+pub struct SvgPicture {
+    pub width: Float,
+    pub height: Float,
+    pub background: [u8; 3],
+    pub color_by: ColorBy,
+    /// Whether to draw the path of each body.
+    pub paths: bool,
+    /// Only every `every`th step adds to the paths, starting with the first.
+    pub every: usize,
+    pub scale: AutoScale,
+    observed: usize,
+    latest: Vec<Mass>,
+    /// Where each body has been, in the order bodies were first seen.
+    history: Vec<(u64, Vec<Point>)>,
+    rows: HashMap<u64, usize>,
+}
+
+impl SvgPicture {
+    pub fn new(width: Float, height: Float) -> SvgPicture {
+        SvgPicture {
+            width,
+            height,
+            background: [0, 0, 0],
+            color_by: ColorBy::Auto,
+            paths: false,
+            every: 1,
+            scale: AutoScale::default(),
+            observed: 0,
+            latest: Vec::new(),
+            history: Vec::new(),
+            rows: HashMap::new(),
+        }
+    }
+
+    /// Takes in `sim` as it is now. The last state observed is the one drawn, whatever the step.
+    pub fn observe(&mut self, sim: &dyn Simulator) {
+        let step = self.observed;
+        self.observed += 1;
+        self.latest = sim.mass_iter().cloned().collect();
+        self.scale.update(self.latest.iter());
+        if !self.paths || step % self.every.max(1) != 0 {
+            return;
+        }
+        for m in self.latest.iter() {
+            let history = &mut self.history;
+            let row = *self.rows.entry(m.id).or_insert_with(|| {
+                history.push((m.id, Vec::new()));
+                history.len() - 1
+            });
+            self.history[row].1.push(m.position);
+        }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (width, height) = (self.width, self.height);
+        writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"0 0 {} {}\">",
+            width, height, width, height
+        )?;
+        writeln!(
+            out,
+            "  <rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+            rgb(self.background)
+        )?;
+
+        let color_by = self.color_by.pick(self.latest.iter());
+        let colors: HashMap<u64, [u8; 3]> = self
+            .latest
+            .iter()
+            .map(|m| (m.id, to_bytes(body_color(m, color_by, &self.scale))))
+            .collect();
+
+        let ends: HashMap<u64, Point> = self.latest.iter().map(|m| (m.id, m.position)).collect();
+        for (id, path) in self.history.iter() {
+            // paths run on to where the body is drawn, even between the steps kept
+            let end = ends.get(id).filter(|end| path.last() != Some(end));
+            if path.len() + end.iter().count() < 2 {
+                continue;
+            }
+            // bodies gone by the end are drawn in grey
+            let color = colors.get(id).cloned().unwrap_or([128, 128, 128]);
+            write!(
+                out,
+                "  <polyline fill=\"none\" stroke=\"{}\" stroke-opacity=\"0.5\" points=\"",
+                rgb(color)
+            )?;
+            for (i, position) in path.iter().chain(end).enumerate() {
+                let p = self.scale.to_screen(*position, width, height);
+                let gap = if i > 0 { " " } else { "" };
+                write!(out, "{}{:.2},{:.2}", gap, p.0, p.1)?;
+            }
+            writeln!(out, "\"/>")?;
+        }
+
+        for m in self.latest.iter() {
+            let p = self.scale.to_screen(m.position, width, height);
+            writeln!(
+                out,
+                "  <circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"{}\"/>",
+                p.0,
+                p.1,
+                body_size(m) / 2.0,
+                rgb(colors[&m.id])
+            )?;
+        }
+        writeln!(out, "</svg>")
+    }
+}
+
+fn rgb(color: [u8; 3]) -> String {
+    format!("rgb({},{},{})", color[0], color[1], color[2])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+
+    fn simulator() -> Box<dyn Simulator> {
+        NoGravityFactory {}.with_masses(vec![
+            Mass {
+                position: Point(-10.0, 0.0),
+                velocity: Point(1.0, 0.0),
+                mass: 1.0,
+                charge: 0.0,
+                id: 1,
+            },
+            Mass {
+                position: Point(10.0, 0.0),
+                velocity: Point::ZERO,
+                mass: 0.4,
+                charge: 0.0,
+                id: 2,
+            },
+        ])
+    }
+
+    fn draw(picture: &SvgPicture) -> String {
+        let mut out = Vec::new();
+        picture.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let sim = simulator();
+        let mut picture = SvgPicture::new(200.0, 100.0);
+        picture.observe(&*sim);
+        let svg = draw(&picture);
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"200\""));
+        assert!(svg.contains("<circle cx=\"5.00\" cy=\"50.00\" r=\"5.00\" fill=\"rgb(0,255,0)\"/>"));
+        assert!(
+            svg.contains("<circle cx=\"195.00\" cy=\"50.00\" r=\"2.00\" fill=\"rgb(255,0,0)\"/>")
+        );
+        assert!(!svg.contains("polyline"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut sim = simulator();
+        let mut picture = SvgPicture::new(200.0, 200.0);
+        picture.paths = true;
+        picture.every = 2;
+        picture.observe(&*sim);
+        for _i in 0..4 {
+            sim.step();
+            picture.observe(&*sim);
+        }
+        sim.remove_mass(2);
+        sim.step();
+        picture.observe(&*sim);
+        let svg = draw(&picture);
+
+        let lines: Vec<&str> = svg.lines().filter(|l| l.contains("polyline")).collect();
+        assert_eq!(lines.len(), 2);
+        // the moving body, seen at steps 0, 2 and 4 and ending where it is drawn at step 5, and
+        // the one removed in grey
+        let points: Vec<&str> = lines[0]
+            .split("points=\"")
+            .nth(1)
+            .unwrap()
+            .trim_end_matches("\"/>")
+            .split(' ')
+            .collect();
+        assert_eq!(points.len(), 4);
+        assert!(svg.contains(&format!(
+            "<circle cx=\"{}\"",
+            points[3].split(',').next().unwrap()
+        )));
+        assert!(lines[1].contains("rgb(128,128,128)"));
+        assert_eq!(svg.matches("<circle").count(), 1);
+    }
+}
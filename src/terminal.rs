@@ -0,0 +1,373 @@
+use super::view::*;
+use super::*;
+use std::time::{Duration, Instant};
+
+/// Dots a braille character is made of, two across and four down, as the bit each sets.
+const BRAILLE_DOTS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/// Character cells drawn in braille, so each holds a grid of 2 by 4 dots. A cell takes the color
+/// of the last dot put in it.
+#[derive(Debug, Clone)]
+pub struct BrailleCanvas {
+    pub cols: usize,
+    pub rows: usize,
+    dots: Vec<u8>,
+    colors: Vec<[u8; 3]>,
+}
+
+impl BrailleCanvas {
+    pub fn new(cols: usize, rows: usize) -> BrailleCanvas {
+        BrailleCanvas {
+            cols,
+            rows,
+            dots: vec![0; cols * rows],
+            colors: vec![[255, 255, 255]; cols * rows],
+        }
+    }
+
+    /// Dots across and down.
+    pub fn dot_size(&self) -> (usize, usize) {
+        (self.cols * 2, self.rows * 4)
+    }
+
+    /// Sets the dot at `x`, `y`; dots off the canvas are dropped.
+    pub fn plot(&mut self, x: Float, y: Float, color: [u8; 3]) {
+        let (width, height) = self.dot_size();
+        if !(x >= 0.0 && y >= 0.0 && x < width as Float && y < height as Float) {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let cell = (y / 4) * self.cols + x / 2;
+        self.dots[cell] |= BRAILLE_DOTS[x % 2][y % 4];
+        self.colors[cell] = color;
+    }
+
+    /// The canvas as lines of text, colored with 24 bit ANSI escapes.
+    pub fn lines(&self) -> Vec<String> {
+        (0..self.rows)
+            .map(|row| {
+                let mut line = String::new();
+                let mut color = None;
+                for cell in row * self.cols..(row + 1) * self.cols {
+                    let dots = self.dots[cell];
+                    if dots == 0 {
+                        line.push(' ');
+                        continue;
+                    }
+                    if color != Some(self.colors[cell]) {
+                        let [r, g, b] = self.colors[cell];
+                        line.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+                        color = Some(self.colors[cell]);
+                    }
+                    line.push(std::char::from_u32(0x2800 + dots as u32).unwrap());
+                }
+                if color.is_some() {
+                    line.push_str("\x1b[0m");
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// What a key pressed in the terminal viewer does.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Key {
+    Pause,
+    Step,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    Faster,
+    Slower,
+    Quit,
+}
+
+impl Key {
+    pub fn from_byte(byte: u8) -> Option<Key> {
+        match byte {
+            b' ' | b'p' => Some(Key::Pause),
+            b'n' | b'.' => Some(Key::Step),
+            b'+' | b'=' => Some(Key::ZoomIn),
+            b'-' | b'_' => Some(Key::ZoomOut),
+            b'0' => Some(Key::ResetZoom),
+            b'f' | b']' => Some(Key::Faster),
+            b's' | b'[' => Some(Key::Slower),
+            // ctrl-c arrives as a byte while the terminal is raw
+            b'q' | 3 => Some(Key::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// A live view of a simulator for text terminals, kept up to date in place. Bodies are braille
+/// dots colored as in the other viewers; the keys pause, step, zoom and change the pace.
+#[derive(Debug)]
+pub struct TerminalView {
+    /// Character cells given to the picture, not counting the status line.
+    pub cols: usize,
+    pub rows: usize,
+    /// Magnification about the center on top of fitting the bodies.
+    pub zoom: Float,
+    pub color_by: ColorBy,
+    pub paused: bool,
+    /// Steps taken a second while running.
+    pub rate: Float,
+    pub scale: AutoScale,
+    /// Steps taken so far.
+    pub steps: usize,
+    /// Simulation time passed so far.
+    pub time: Float,
+    /// Steps due but not yet taken, including a part step.
+    owed: Float,
+}
+
+impl TerminalView {
+    pub const MIN_RATE: Float = 0.25;
+    pub const MAX_RATE: Float = 1000.0;
+    /// Longest `advance` keeps stepping. A simulator slower than the pace asked for then just
+    /// runs slow, instead of owing ever more steps and never drawing or reading keys again.
+    pub const BUDGET: Duration = Duration::from_millis(100);
+
+    pub fn new(cols: usize, rows: usize) -> TerminalView {
+        TerminalView {
+            cols,
+            rows,
+            zoom: 1.0,
+            color_by: ColorBy::Auto,
+            paused: false,
+            rate: 10.0,
+            scale: AutoScale::default(),
+            steps: 0,
+            time: 0.0,
+            owed: 0.0,
+        }
+    }
+
+    /// Acts on `key`, returning false when it asks to quit.
+    pub fn press(&mut self, key: Key) -> bool {
+        match key {
+            Key::Pause => self.paused = !self.paused,
+            Key::Step => {
+                self.paused = true;
+                self.owed += 1.0;
+            }
+            Key::ZoomIn => self.zoom *= 1.5,
+            Key::ZoomOut => self.zoom /= 1.5,
+            Key::ResetZoom => self.zoom = 1.0,
+            Key::Faster => self.rate = (self.rate * 2.0).min(TerminalView::MAX_RATE),
+            Key::Slower => self.rate = (self.rate / 2.0).max(TerminalView::MIN_RATE),
+            Key::Quit => return false,
+        }
+        true
+    }
+
+    /// Steps `sim` as often as the pace calls for in `elapsed` seconds, plus any single steps
+    /// asked for, giving up on the steps still owed once `BUDGET` is spent.
+    pub fn advance(&mut self, sim: &mut dyn Simulator, elapsed: Float) {
+        if !self.paused {
+            self.owed += self.rate * elapsed;
+        }
+        let start = Instant::now();
+        while self.owed >= 1.0 {
+            sim.step();
+            self.steps += 1;
+            self.time += sim.time_step();
+            self.owed -= 1.0;
+            if start.elapsed() >= TerminalView::BUDGET {
+                self.owed = self.owed.min(1.0);
+                break;
+            }
+        }
+        if self.paused {
+            self.owed = 0.0;
+        }
+    }
+
+    /// A whole frame, starting from the top left of the screen and ending in the status line.
+    pub fn draw(&mut self, sim: &dyn Simulator) -> String {
+        let mut canvas = BrailleCanvas::new(self.cols, self.rows);
+        let masses: Vec<&Mass> = sim.mass_iter().collect();
+        self.scale.update(masses.iter().cloned());
+        let color_by = self.color_by.pick(masses.iter().cloned());
+
+        let (width, height) = canvas.dot_size();
+        let (width, height) = (width as Float, height as Float);
+        let center = Point(width / 2.0, height / 2.0);
+        for m in masses.iter() {
+            let p = center + (self.scale.to_screen(m.position, width, height) - center) * self.zoom;
+            canvas.plot(p.0, p.1, to_bytes(body_color(m, color_by, &self.scale)));
+        }
+
+        let mut frame = String::from("\x1b[H");
+        for line in canvas.lines() {
+            frame.push_str(&line);
+            frame.push_str("\x1b[K\r\n");
+        }
+        let mut status = format!(
+            " step {}  time {:.1}  bodies {}  zoom {:.2}x  {} steps/s{} ",
+            self.steps,
+            self.time,
+            masses.len(),
+            self.zoom,
+            self.rate,
+            if self.paused { "  paused" } else { "" }
+        );
+        let help = "  space pause  n step  +/- zoom  f/s speed  q quit";
+        if status.chars().count() + help.len() <= self.cols {
+            status.push_str(help);
+        }
+        // a status line wider than the screen would wrap and scroll the picture away
+        let status: String = status.chars().take(self.cols).collect();
+        frame.push_str(&format!("\x1b[7m{}\x1b[0m\x1b[K", status));
+        frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+
+    fn simulator() -> Box<dyn Simulator> {
+        NoGravityFactory {}.with_masses(vec![
+            Mass {
+                position: Point(-10.0, 0.0),
+                velocity: Point(1.0, 0.0),
+                mass: 1.0,
+                charge: 0.0,
+                id: 1,
+            },
+            Mass {
+                position: Point(10.0, 0.0),
+                velocity: Point::ZERO,
+                mass: 1.0,
+                charge: 0.0,
+                id: 2,
+            },
+        ])
+    }
+
+    /// A simulator that takes 10 ms over each step of nothing.
+    #[derive(Debug)]
+    struct Slow(Vec<Mass>);
+
+    impl Simulator for Slow {
+        fn step(&mut self) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        fn add_field(&mut self, _field: Box<dyn ExternalField>) {}
+        fn mass_iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Mass> + 'a> {
+            Box::new(self.0.iter())
+        }
+        fn mass_iter_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut Mass> + 'a> {
+            Box::new(self.0.iter_mut())
+        }
+        fn add_mass(&mut self, mass: Mass) {
+            self.0.push(mass);
+        }
+        fn remove_mass(&mut self, _id: u64) -> Option<Mass> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_braille() {
+        let mut canvas = BrailleCanvas::new(2, 1);
+        canvas.plot(0.0, 0.0, [255, 0, 0]);
+        canvas.plot(1.5, 3.9, [255, 0, 0]);
+        canvas.plot(-1.0, 0.0, [0, 0, 255]);
+        canvas.plot(4.0, 0.0, [0, 0, 255]);
+        assert_eq!(canvas.lines(), vec!["\x1b[38;2;255;0;0m\u{2881} \x1b[0m"]);
+
+        let mut canvas = BrailleCanvas::new(3, 2);
+        canvas.plot(0.0, 0.0, [1, 2, 3]);
+        canvas.plot(2.0, 0.0, [1, 2, 3]);
+        canvas.plot(5.0, 7.0, [4, 5, 6]);
+        let lines = canvas.lines();
+        assert_eq!(lines[0], "\x1b[38;2;1;2;3m\u{2801}\u{2801} \x1b[0m");
+        assert_eq!(lines[1], "  \x1b[38;2;4;5;6m\u{2880}\x1b[0m");
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut view = TerminalView::new(10, 5);
+        assert_eq!(Key::from_byte(b' '), Some(Key::Pause));
+        assert_eq!(Key::from_byte(3), Some(Key::Quit));
+        assert_eq!(Key::from_byte(b'x'), None);
+
+        assert!(view.press(Key::Pause));
+        assert!(view.paused);
+        view.press(Key::ZoomIn);
+        assert_eq!(view.zoom, 1.5);
+        view.press(Key::ResetZoom);
+        assert_eq!(view.zoom, 1.0);
+        for _i in 0..20 {
+            view.press(Key::Slower);
+        }
+        assert_eq!(view.rate, TerminalView::MIN_RATE);
+        assert!(!view.press(Key::Quit));
+    }
+
+    #[test]
+    fn test_pace() {
+        let mut sim = simulator();
+        let mut view = TerminalView::new(10, 5);
+        view.rate = 4.0;
+        view.advance(&mut *sim, 0.6);
+        assert_eq!(view.steps, 2);
+        view.advance(&mut *sim, 0.25);
+        assert_eq!(view.steps, 3);
+
+        view.press(Key::Pause);
+        view.advance(&mut *sim, 10.0);
+        assert_eq!(view.steps, 3);
+        view.press(Key::Step);
+        view.advance(&mut *sim, 10.0);
+        assert_eq!(view.steps, 4);
+        assert_eq!(view.time, 4.0);
+        assert_eq!(sim.find_mass(1).unwrap().position, Point(-6.0, 0.0));
+    }
+
+    #[test]
+    fn test_falling_behind() {
+        let mut sim = Slow(Vec::new());
+        let mut view = TerminalView::new(10, 5);
+        view.rate = TerminalView::MAX_RATE;
+        // each frame lasts as long as its steps took, which would owe more steps every time
+        let mut elapsed = 0.05;
+        for _frame in 0..5 {
+            let start = Instant::now();
+            let before = view.steps;
+            view.advance(&mut sim, elapsed);
+            assert!(view.steps - before <= 20, "{}", view.steps - before);
+            elapsed = start.elapsed().as_secs_f64();
+        }
+        assert!(view.owed <= 1.0);
+    }
+
+    #[test]
+    fn test_draw() {
+        let sim = simulator();
+        let mut view = TerminalView::new(60, 5);
+        let frame = view.draw(&*sim);
+        let lines: Vec<&str> = frame.split("\r\n").collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("\x1b[H"));
+        // both bodies sit on the middle row, at the ends, moving green and still red
+        assert!(lines[2].starts_with(" \x1b[38;2;0;255;0m\u{2820}"));
+        assert!(lines[2].contains("\x1b[38;2;255;0;0m\u{2820} \x1b[0m"));
+        assert!(lines[5].contains("bodies 2"));
+        assert!(!lines[5].contains("q quit"));
+
+        let mut narrow = TerminalView::new(20, 5);
+        let frame = narrow.draw(&*sim);
+        let status = frame.split("\r\n").last().unwrap();
+        assert_eq!(status.chars().count(), "\x1b[7m\x1b[0m\x1b[K".len() + 20);
+
+        view.press(Key::ZoomIn);
+        view.press(Key::ZoomIn);
+        let frame = view.draw(&*sim);
+        assert!(!frame.contains("\x1b[38;2"));
+    }
+}
@@ -0,0 +1,593 @@
+use super::*;
+use palette::{Gradient, Hsv, LinSrgb};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+
+/// Fits a picture to the bodies. Keeps the largest extent and speed seen so far, so the picture
+/// only ever zooms out and colors mean the same speed from one frame to the next.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AutoScale {
+    /// Largest distance of any body from the origin along either axis.
+    pub size: Float,
+    pub speed: Float,
+    pub mass: Float,
+}
+
+impl AutoScale {
+    pub fn update<'a, I: Iterator<Item = &'a Mass>>(&mut self, masses: I) {
+        for m in masses {
+            self.size = self.size.max(m.position.0.abs()).max(m.position.1.abs());
+            self.speed = self.speed.max(m.velocity.magnitude());
+            self.mass = self.mass.max(m.mass);
+        }
+    }
+
+    /// Where `position` lands on a `width` by `height` picture centered on the origin, with a
+    /// margin of 5% around the farthest body.
+    pub fn to_screen(&self, position: Point, width: Float, height: Float) -> Point {
+        let size = if self.size > 0.0 { self.size } else { 1.0 };
+        Point(
+            position.0 * width * 0.95 / size / 2.0 + width / 2.0,
+            position.1 * height * 0.95 / size / 2.0 + height / 2.0,
+        )
+    }
+
+    /// Speed of `velocity` as a share of the fastest seen.
+    pub fn speed_fraction(&self, velocity: Point) -> Float {
+        if self.speed > 0.0 {
+            (velocity.magnitude() / self.speed).min(1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Mass of `m` as a share of the heaviest seen.
+    pub fn mass_fraction(&self, m: &Mass) -> Float {
+        if self.mass > 0.0 {
+            (m.mass / self.mass).min(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Mass weighted middle of `masses`, or their plain middle when none has mass.
+pub fn center_of_mass(masses: &[&Mass]) -> Option<Point> {
+    if masses.is_empty() {
+        return None;
+    }
+    let total: Float = masses.iter().map(|m| m.mass).sum();
+    if total > 0.0 {
+        Some(
+            masses
+                .iter()
+                .fold(Point::ZERO, |p, m| p + m.position * m.mass)
+                / total,
+        )
+    } else {
+        Some(masses.iter().fold(Point::ZERO, |p, m| p + m.position) / masses.len() as Float)
+    }
+}
+
+/// What the camera keeps in view from one frame to the next.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CameraMode {
+    /// Stays wherever panning and zooming left it.
+    Free,
+    /// Frames every body.
+    FitAll,
+    /// Frames the smallest circle about the center of mass holding this share of the mass, so a
+    /// few escaping bodies don't shrink the rest to a dot.
+    FitMass(Float),
+    /// Keeps the center of mass in the middle at the current zoom.
+    CenterOfMass,
+    /// Keeps the body with this id in the middle at the current zoom, and stays put once it's gone.
+    Body(u64),
+}
+
+/// Where a viewer looks and how closely. Unlike `AutoScale` it can zoom back in, and can be
+/// moved by hand.
+#[derive(Debug, Copy, Clone)]
+pub struct Camera {
+    /// Point of the simulation in the middle of the view.
+    pub center: Point,
+    /// Pixels per unit of distance.
+    pub zoom: Float,
+    pub mode: CameraMode,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            center: Point::ZERO,
+            zoom: 1.0,
+            mode: CameraMode::FitAll,
+        }
+    }
+}
+
+impl Camera {
+    /// Share of the view fitted bodies take up, leaving a margin around them.
+    const FILL: Float = 0.95;
+
+    /// Moves the camera as its mode asks for the bodies as they are now.
+    pub fn update(&mut self, masses: &[&Mass], width: Float, height: Float) {
+        let finite: Vec<&Mass> = masses
+            .iter()
+            .filter(|m| m.position.0.is_finite() && m.position.1.is_finite())
+            .cloned()
+            .collect();
+        match self.mode {
+            CameraMode::Free => {}
+            CameraMode::FitAll => {
+                if finite.is_empty() {
+                    return;
+                }
+                let mut min = finite[0].position;
+                let mut max = min;
+                for m in finite.iter() {
+                    min = Point(min.0.min(m.position.0), min.1.min(m.position.1));
+                    max = Point(max.0.max(m.position.0), max.1.max(m.position.1));
+                }
+                self.center = (min + max) / 2.0;
+                let span = max - min;
+                let zoom = (width / span.0).min(height / span.1) * Camera::FILL;
+                if zoom.is_finite() {
+                    self.zoom = zoom;
+                }
+            }
+            CameraMode::FitMass(share) => {
+                let center = match center_of_mass(&finite) {
+                    Some(center) => center,
+                    None => return,
+                };
+                self.center = center;
+                let mut by_distance: Vec<(Float, Float)> = finite
+                    .iter()
+                    .map(|m| ((m.position - center).magnitude(), m.mass))
+                    .collect();
+                by_distance.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                // massless bodies count one each when there is no mass to go by
+                let weightless = by_distance.iter().all(|(_, mass)| *mass <= 0.0);
+                let weight = |mass: Float| if weightless { 1.0 } else { mass };
+                let total: Float = by_distance.iter().map(|(_, mass)| weight(*mass)).sum();
+                let mut held = 0.0;
+                for (distance, mass) in by_distance {
+                    held += weight(mass);
+                    if held >= share * total {
+                        if distance > 0.0 {
+                            self.zoom = width.min(height) * Camera::FILL / (2.0 * distance);
+                        }
+                        break;
+                    }
+                }
+            }
+            CameraMode::CenterOfMass => {
+                if let Some(center) = center_of_mass(&finite) {
+                    self.center = center;
+                }
+            }
+            CameraMode::Body(id) => {
+                if let Some(m) = finite.iter().find(|m| m.id == id) {
+                    self.center = m.position;
+                }
+            }
+        }
+    }
+
+    pub fn to_screen(&self, position: Point, width: Float, height: Float) -> Point {
+        (position - self.center) * self.zoom + Point(width / 2.0, height / 2.0)
+    }
+
+    pub fn to_world(&self, screen: Point, width: Float, height: Float) -> Point {
+        (screen - Point(width / 2.0, height / 2.0)) / self.zoom + self.center
+    }
+
+    /// Zooms in by `factor`, or out when it is under one, keeping the point under `screen`
+    /// where it is. Fitting would undo the zoom, so it stops; following zooms about the middle
+    /// instead, so the followed point stays there.
+    pub fn zoom_at(&mut self, factor: Float, screen: Point, width: Float, height: Float) {
+        match self.mode {
+            CameraMode::CenterOfMass | CameraMode::Body(_) => self.zoom *= factor,
+            _ => {
+                let fixed = self.to_world(screen, width, height);
+                self.zoom *= factor;
+                self.center = fixed - (screen - Point(width / 2.0, height / 2.0)) / self.zoom;
+                self.mode = CameraMode::Free;
+            }
+        }
+    }
+
+    /// Moves the view with a drag of `delta` pixels, and stops following or fitting.
+    pub fn pan(&mut self, delta: Point) {
+        self.center -= delta / self.zoom;
+        self.mode = CameraMode::Free;
+    }
+
+    /// The body drawn nearest `screen`, if any is within `reach` pixels.
+    pub fn nearest(
+        &self,
+        masses: &[&Mass],
+        screen: Point,
+        reach: Float,
+        width: Float,
+        height: Float,
+    ) -> Option<u64> {
+        masses
+            .iter()
+            .map(|m| {
+                let offset = self.to_screen(m.position, width, height) - screen;
+                (offset.magnitude(), m.id)
+            })
+            .filter(|(distance, _)| *distance <= reach)
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, id)| id)
+    }
+}
+
+/// The last few positions of each body, one for each time they were recorded, kept to draw where
+/// they have been. Recording once a step makes a trail the same length in steps however fast it
+/// is shown.
+#[derive(Debug, Clone, Default)]
+pub struct Trails {
+    /// Positions kept for each body; the oldest go first.
+    pub length: usize,
+    history: HashMap<u64, VecDeque<Point>>,
+}
+
+impl Trails {
+    pub fn new(length: usize) -> Trails {
+        Trails {
+            length,
+            history: HashMap::new(),
+        }
+    }
+
+    /// Adds where each of `masses` is now, forgetting bodies that are gone.
+    pub fn record(&mut self, masses: &[&Mass]) {
+        let ids: HashSet<u64> = masses.iter().map(|m| m.id).collect();
+        self.history.retain(|id, _| ids.contains(id));
+        if self.length == 0 {
+            return;
+        }
+        for m in masses.iter() {
+            let trail = self.history.entry(m.id).or_default();
+            while trail.len() >= self.length {
+                trail.pop_front();
+            }
+            trail.push_back(m.position);
+        }
+    }
+
+    /// Past positions of body `id`, oldest first, each with an opacity that fades with age.
+    pub fn trail(&self, id: u64) -> impl Iterator<Item = (Point, Float)> + '_ {
+        let trail = self.history.get(&id);
+        let count = trail.map_or(0, |trail| trail.len());
+        trail
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(move |(i, p)| (*p, (i + 1) as Float / (count + 1) as Float))
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+}
+
+/// What the color of a body shows.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ColorBy {
+    /// By charge when any body is charged, by speed otherwise.
+    #[default]
+    Auto,
+    Speed,
+    Mass,
+    Charge,
+}
+
+impl ColorBy {
+    /// Settles `Auto` for these bodies; the other choices stand as they are.
+    pub fn pick<'a, I: Iterator<Item = &'a Mass>>(self, mut masses: I) -> ColorBy {
+        match self {
+            ColorBy::Auto if masses.any(|m| m.charge != 0.0) => ColorBy::Charge,
+            ColorBy::Auto => ColorBy::Speed,
+            other => other,
+        }
+    }
+}
+
+impl FromStr for ColorBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<ColorBy, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ColorBy::Auto),
+            "speed" | "velocity" => Ok(ColorBy::Speed),
+            "mass" => Ok(ColorBy::Mass),
+            "charge" => Ok(ColorBy::Charge),
+            _ => Err(format!(
+                "unknown coloring {}, expected auto, speed, mass or charge",
+                s
+            )),
+        }
+    }
+}
+
+/// Red for the slowest bodies through to green for the fastest.
+pub fn speed_color(fraction: Float) -> LinSrgb<f64> {
+    let gradient = Gradient::new(vec![
+        Hsv::from(LinSrgb::new(1.0, 0.0, 0.0)),
+        Hsv::from(LinSrgb::new(0.0, 1.0, 0.0)),
+    ]);
+    gradient.get(fraction).into()
+}
+
+pub fn charge_color(charge: Float) -> LinSrgb<f64> {
+    if charge > 0.0 {
+        LinSrgb::new(1.0, 0.2, 0.2)
+    } else if charge < 0.0 {
+        LinSrgb::new(0.2, 0.4, 1.0)
+    } else {
+        LinSrgb::new(0.6, 0.6, 0.6)
+    }
+}
+
+/// Colors a body by what `color_by` asks, on the red to green gradient for speed and mass.
+/// `Auto` is taken as speed; settle it with `ColorBy::pick` first.
+pub fn body_color(m: &Mass, color_by: ColorBy, scale: &AutoScale) -> LinSrgb<f64> {
+    match color_by {
+        ColorBy::Auto | ColorBy::Speed => speed_color(scale.speed_fraction(m.velocity)),
+        ColorBy::Mass => speed_color(scale.mass_fraction(m)),
+        ColorBy::Charge => charge_color(m.charge),
+    }
+}
+
+/// A color as 8 bit red, green and blue.
+pub fn to_bytes(color: LinSrgb<f64>) -> [u8; 3] {
+    let byte = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [byte(color.red), byte(color.green), byte(color.blue)]
+}
+
+/// Side of the square a body is drawn as; test particles still get a pixel.
+pub fn body_size(m: &Mass) -> Float {
+    (m.mass * 10.0).max(1.0)
+}
+
+/// Names of what `inspect` tells about a body, in its order.
+pub const INSPECTED: [&str; 7] = [
+    "Id",
+    "Mass",
+    "Position",
+    "Velocity",
+    "Speed",
+    "Kinetic energy",
+    "Acceleration",
+];
+
+/// What a viewer shows about a selected body, as named in `INSPECTED`. The acceleration is only
+/// known for engines with gravity and is shown as a dash otherwise.
+pub fn inspect(m: &Mass, acceleration: Option<Point>) -> [String; 7] {
+    let point = |p: Point| format!("({:.3}, {:.3})", p.0, p.1);
+    let speed = m.velocity.magnitude();
+    [
+        m.id.to_string(),
+        format!("{:.3}", m.mass),
+        point(m.position),
+        point(m.velocity),
+        format!("{:.3}", speed),
+        format!("{:.3}", 0.5 * m.mass * speed * speed),
+        acceleration.map_or_else(|| "-".to_string(), point),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn body(position: Point, velocity: Point) -> Mass {
+        Mass {
+            position,
+            velocity,
+            mass: 1.0,
+            charge: 0.0,
+            id: 0,
+        }
+    }
+
+    #[test]
+    fn test_auto_scale() {
+        let mut scale = AutoScale::default();
+        assert_eq!(scale.to_screen(Point::ZERO, 100.0, 50.0), Point(50.0, 25.0));
+
+        scale.update([body(Point(-20.0, 5.0), Point(3.0, 4.0))].iter());
+        scale.update([body(Point(1.0, 1.0), Point::ZERO)].iter());
+        assert_eq!(scale.size, 20.0);
+        assert_eq!(scale.speed, 5.0);
+
+        let corner = scale.to_screen(Point(-20.0, 20.0), 100.0, 100.0);
+        assert!((corner.0 - 2.5).abs() < 1e-12 && (corner.1 - 97.5).abs() < 1e-12);
+        assert_eq!(scale.speed_fraction(Point(0.0, 2.5)), 0.5);
+        assert_eq!(
+            scale.mass_fraction(&Mass {
+                mass: 0.25,
+                ..body(Point::ZERO, Point::ZERO)
+            }),
+            0.25
+        );
+    }
+
+    #[test]
+    fn test_color_by() {
+        let neutral = body(Point::ZERO, Point::ZERO);
+        let charged = Mass {
+            charge: 1.0,
+            ..neutral
+        };
+        assert_eq!(ColorBy::Auto.pick([neutral].iter()), ColorBy::Speed);
+        assert_eq!(
+            ColorBy::Auto.pick([neutral, charged].iter()),
+            ColorBy::Charge
+        );
+        assert_eq!(ColorBy::Mass.pick([charged].iter()), ColorBy::Mass);
+        assert_eq!("velocity".parse::<ColorBy>(), Ok(ColorBy::Speed));
+        assert!("spin".parse::<ColorBy>().is_err());
+    }
+
+    fn heavy(id: u64, position: Point, mass: Float) -> Mass {
+        Mass {
+            id,
+            mass,
+            ..body(position, Point::ZERO)
+        }
+    }
+
+    #[test]
+    fn test_camera_fits() {
+        let masses = [
+            heavy(1, Point(-10.0, 0.0), 1.0),
+            heavy(2, Point(10.0, 5.0), 1.0),
+            heavy(3, Point(0.0, -5.0), 1.0),
+            heavy(4, Point(1000.0, 0.0), 0.01),
+        ];
+        let all: Vec<&Mass> = masses.iter().collect();
+        let mut camera = Camera::default();
+        camera.update(&all, 200.0, 100.0);
+        assert_eq!(camera.center, Point(495.0, 0.0));
+        assert!((camera.zoom - 200.0 * 0.95 / 1010.0).abs() < 1e-12);
+
+        // the escaping body, with a fraction of a percent of the mass, is left out
+        camera.mode = CameraMode::FitMass(0.9);
+        camera.update(&all, 200.0, 100.0);
+        let center = center_of_mass(&all).unwrap();
+        assert!((camera.center - center).magnitude() < 1e-12);
+        let farthest = masses[..3]
+            .iter()
+            .map(|m| (m.position - center).magnitude())
+            .fold(0.0, Float::max);
+        assert!((camera.zoom - 100.0 * 0.95 / (2.0 * farthest)).abs() < 1e-12);
+        for m in masses[..3].iter() {
+            let p = camera.to_screen(m.position, 200.0, 100.0);
+            assert!((p - Point(100.0, 50.0)).magnitude() <= 47.5 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_camera_follows() {
+        let masses = [
+            heavy(1, Point(4.0, 4.0), 3.0),
+            heavy(2, Point(-4.0, 0.0), 1.0),
+        ];
+        let all: Vec<&Mass> = masses.iter().collect();
+        let mut camera = Camera {
+            zoom: 2.0,
+            mode: CameraMode::CenterOfMass,
+            ..Camera::default()
+        };
+        camera.update(&all, 100.0, 100.0);
+        assert_eq!(camera.center, Point(2.0, 3.0));
+        assert_eq!(camera.zoom, 2.0);
+
+        camera.mode = CameraMode::Body(2);
+        camera.update(&all, 100.0, 100.0);
+        assert_eq!(
+            camera.to_screen(masses[1].position, 100.0, 100.0),
+            Point(50.0, 50.0)
+        );
+        camera.zoom_at(2.0, Point(0.0, 0.0), 100.0, 100.0);
+        assert_eq!(camera.mode, CameraMode::Body(2));
+        assert_eq!(camera.zoom, 4.0);
+
+        // a body that has gone leaves the camera where it was
+        camera.update(&all[..1], 100.0, 100.0);
+        assert_eq!(camera.center, Point(-4.0, 0.0));
+    }
+
+    #[test]
+    fn test_camera_by_hand() {
+        let mut camera = Camera::default();
+        let under = Point(30.0, 80.0);
+        let before = camera.to_world(under, 100.0, 100.0);
+        camera.zoom_at(1.5, under, 100.0, 100.0);
+        assert_eq!(camera.mode, CameraMode::Free);
+        assert!((camera.to_world(under, 100.0, 100.0) - before).magnitude() < 1e-12);
+
+        // dragging right brings what was to the left into view
+        camera.zoom = 2.0;
+        camera.center = Point::ZERO;
+        camera.pan(Point(10.0, 0.0));
+        assert_eq!(camera.center, Point(-5.0, 0.0));
+
+        let masses = [
+            heavy(1, Point(-5.0, 0.0), 1.0),
+            heavy(2, Point(0.0, 0.0), 1.0),
+        ];
+        let all: Vec<&Mass> = masses.iter().collect();
+        assert_eq!(
+            camera.nearest(&all, Point(52.0, 49.0), 5.0, 100.0, 100.0),
+            Some(1)
+        );
+        assert_eq!(
+            camera.nearest(&all, Point(0.0, 0.0), 5.0, 100.0, 100.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_trails() {
+        let mut trails = Trails::new(3);
+        let mut m = Mass {
+            id: 7,
+            ..body(Point::ZERO, Point::ZERO)
+        };
+        for x in 0..5 {
+            m.position = Point(x as Float, 0.0);
+            trails.record(&[&m]);
+        }
+        let trail: Vec<(Point, Float)> = trails.trail(7).collect();
+        assert_eq!(
+            trail,
+            vec![
+                (Point(2.0, 0.0), 0.25),
+                (Point(3.0, 0.0), 0.5),
+                (Point(4.0, 0.0), 0.75)
+            ]
+        );
+
+        trails.record(&[]);
+        assert_eq!(trails.trail(7).count(), 0);
+        trails.length = 0;
+        trails.record(&[&m]);
+        assert_eq!(trails.trail(7).count(), 0);
+    }
+
+    #[test]
+    fn test_inspect() {
+        let m = Mass {
+            id: 4,
+            mass: 2.0,
+            ..body(Point(1.0, -2.0), Point(3.0, 4.0))
+        };
+        let shown = inspect(&m, Some(Point(0.5, 0.0)));
+        assert_eq!(shown[0], "4");
+        assert_eq!(shown[2], "(1.000, -2.000)");
+        assert_eq!(shown[4], "5.000");
+        assert_eq!(shown[5], "25.000");
+        assert_eq!(shown[6], "(0.500, 0.000)");
+        assert_eq!(inspect(&m, None)[6], "-");
+    }
+
+    #[test]
+    fn test_colors() {
+        let slow = speed_color(0.0);
+        let fast = speed_color(1.0);
+        assert!(slow.red > 0.99 && slow.green < 0.01);
+        assert!(fast.green > 0.99 && fast.red < 0.01);
+        assert_eq!(charge_color(-1.0), LinSrgb::new(0.2, 0.4, 1.0));
+        assert_eq!(to_bytes(LinSrgb::new(1.0, 0.2, 2.0)), [255, 51, 255]);
+        assert_eq!(
+            body_size(&Mass::new_test_particle(Point::ZERO, Point::ZERO)),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use super::recorder::Filter;
+use super::*;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Writes the state of a simulator as a series of VTK PolyData files, one `.vtp` per recorded
+/// step, with a `.pvd` index that ParaView opens as a single time series. Each body becomes a
+/// vertex carrying its mass, velocity and id.
+pub struct VtkSeries {
+    dir: PathBuf,
+    name: String,
+    /// Only every `every`th step is written, starting with the first.
+    pub every: usize,
+    /// Bodies to write; all of them when there is no filter.
+    pub filter: Option<Filter>,
+    observed: usize,
+    time: Float,
+    /// Time and file name of every step written so far.
+    datasets: Vec<(Float, String)>,
+}
+
+impl Debug for VtkSeries {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("VtkSeries")
+            .field("dir", &self.dir)
+            .field("name", &self.name)
+            .field("every", &self.every)
+            .field("filtered", &self.filter.is_some())
+            .field("observed", &self.observed)
+            .field("time", &self.time)
+            .field("datasets", &self.datasets.len())
+            .finish()
+    }
+}
+
+impl VtkSeries {
+    /// A series of files named after `name` in `dir`, which is made if it isn't there.
+    pub fn new<P: Into<PathBuf>>(dir: P, name: &str) -> io::Result<VtkSeries> {
+        let dir = dir.into();
+        create_dir_all(&dir)?;
+        Ok(VtkSeries {
+            dir,
+            name: name.to_string(),
+            every: 1,
+            filter: None,
+            observed: 0,
+            time: 0.0,
+            datasets: Vec::new(),
+        })
+    }
+
+    /// Writes the initial state, then steps `sim` `steps` times writing each new state, then the
+    /// index.
+    pub fn run(&mut self, sim: &mut dyn Simulator, steps: usize) -> io::Result<()> {
+        self.observe(sim)?;
+        for _i in 0..steps {
+            sim.step();
+            self.observe(sim)?;
+        }
+        self.finish()
+    }
+
+    /// Writes `sim` as it is now, counting steps and time as a `Recorder` does.
+    pub fn observe(&mut self, sim: &dyn Simulator) -> io::Result<()> {
+        if self.observed > 0 {
+            self.time += sim.time_step();
+        }
+        let step = self.observed;
+        self.observed += 1;
+        if step % self.every.max(1) != 0 {
+            return Ok(());
+        }
+
+        let masses: Vec<&Mass> = sim
+            .mass_iter()
+            .filter(|x| self.filter.as_ref().map_or(true, |keep| keep(x)))
+            .collect();
+        let file = format!("{}_{:06}.vtp", self.name, step);
+        let mut out = BufWriter::new(File::create(self.dir.join(&file))?);
+        write_polydata(&mut out, &masses)?;
+        out.flush()?;
+        self.datasets.push((self.time, file));
+        Ok(())
+    }
+
+    /// Writes the `.pvd` index of every step so far. Can be called again as the series grows.
+    pub fn finish(&self) -> io::Result<()> {
+        let path = self.dir.join(format!("{}.pvd", self.name));
+        let mut out = BufWriter::new(File::create(path)?);
+        write_collection(&mut out, &self.datasets)?;
+        out.flush()
+    }
+}
+
+/// Writes `masses` as an XML PolyData file of vertices.
+pub fn write_polydata<W: Write>(out: &mut W, masses: &[&Mass]) -> io::Result<()> {
+    let n = masses.len();
+    writeln!(out, "<?xml version=\"1.0\"?>")?;
+    writeln!(
+        out,
+        "<VTKFile type=\"PolyData\" version=\"0.1\" byte_order=\"LittleEndian\">"
+    )?;
+    writeln!(out, "  <PolyData>")?;
+    writeln!(
+        out,
+        "    <Piece NumberOfPoints=\"{}\" NumberOfVerts=\"{}\" NumberOfLines=\"0\" \
+         NumberOfStrips=\"0\" NumberOfPolys=\"0\">",
+        n, n
+    )?;
+
+    writeln!(
+        out,
+        "      <PointData Scalars=\"mass\" Vectors=\"velocity\">"
+    )?;
+    write_array(out, "Float64", "mass", 1, masses.iter().map(|x| x.mass))?;
+    write_array(
+        out,
+        "Float64",
+        "velocity",
+        3,
+        masses
+            .iter()
+            .flat_map(|x| vec![x.velocity.0, x.velocity.1, 0.0]),
+    )?;
+    write_array(out, "UInt64", "id", 1, masses.iter().map(|x| x.id))?;
+    writeln!(out, "      </PointData>")?;
+
+    writeln!(out, "      <Points>")?;
+    write_array(
+        out,
+        "Float64",
+        "position",
+        3,
+        masses
+            .iter()
+            .flat_map(|x| vec![x.position.0, x.position.1, 0.0]),
+    )?;
+    writeln!(out, "      </Points>")?;
+
+    // every vertex is a cell of its own point
+    writeln!(out, "      <Verts>")?;
+    write_array(out, "Int64", "connectivity", 1, 0..n)?;
+    write_array(out, "Int64", "offsets", 1, 1..=n)?;
+    writeln!(out, "      </Verts>")?;
+
+    writeln!(out, "    </Piece>")?;
+    writeln!(out, "  </PolyData>")?;
+    writeln!(out, "</VTKFile>")
+}
+
+fn write_array<W: Write, T: Display, I: Iterator<Item = T>>(
+    out: &mut W,
+    kind: &str,
+    name: &str,
+    components: usize,
+    values: I,
+) -> io::Result<()> {
+    write!(
+        out,
+        "        <DataArray type=\"{}\" Name=\"{}\" NumberOfComponents=\"{}\" format=\"ascii\">",
+        kind, name, components
+    )?;
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            write!(out, " ")?;
+        }
+        write!(out, "{}", value)?;
+    }
+    writeln!(out, "</DataArray>")
+}
+
+/// Writes a `.pvd` collection listing each file with its time.
+pub fn write_collection<W: Write>(out: &mut W, datasets: &[(Float, String)]) -> io::Result<()> {
+    writeln!(out, "<?xml version=\"1.0\"?>")?;
+    writeln!(out, "<VTKFile type=\"Collection\" version=\"0.1\">")?;
+    writeln!(out, "  <Collection>")?;
+    for (time, file) in datasets.iter() {
+        writeln!(
+            out,
+            "    <DataSet timestep=\"{}\" group=\"\" part=\"0\" file=\"{}\"/>",
+            time, file
+        )?;
+    }
+    writeln!(out, "  </Collection>")?;
+    writeln!(out, "</VTKFile>")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::no_gravity::*;
+    use std::fs::{read_to_string, remove_dir_all};
+
+    fn body(id: u64, position: Point) -> Mass {
+        Mass {
+            position,
+            velocity: Point(0.5, -1.0),
+            mass: 2.0,
+            charge: 0.0,
+            id,
+        }
+    }
+
+    #[test]
+    fn test_polydata() {
+        let masses = [body(4, Point(1.0, 2.0)), body(9, Point(-3.0, 0.25))];
+        let mut out = Vec::new();
+        write_polydata(&mut out, &masses.iter().collect::<Vec<_>>()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("NumberOfPoints=\"2\" NumberOfVerts=\"2\""));
+        assert!(text.contains("Name=\"mass\" NumberOfComponents=\"1\" format=\"ascii\">2 2<"));
+        assert!(text.contains("format=\"ascii\">0.5 -1 0 0.5 -1 0<"));
+        assert!(text.contains("Name=\"id\" NumberOfComponents=\"1\" format=\"ascii\">4 9<"));
+        assert!(text.contains("format=\"ascii\">1 2 0 -3 0.25 0<"));
+        assert!(
+            text.contains("Name=\"connectivity\" NumberOfComponents=\"1\" format=\"ascii\">0 1<")
+        );
+        assert!(text.contains("Name=\"offsets\" NumberOfComponents=\"1\" format=\"ascii\">1 2<"));
+        assert!(text.trim_end().ends_with("</VTKFile>"));
+    }
+
+    #[test]
+    fn test_series() {
+        let dir = std::env::temp_dir().join(format!("space-vtk-{}", std::process::id()));
+        let mut sim =
+            NoGravityFactory {}.with_masses(vec![body(1, Point::ZERO), body(2, Point(10.0, 0.0))]);
+        let mut series = VtkSeries::new(&dir, "run").unwrap();
+        series.every = 2;
+        series.filter = Some(Box::new(|x: &Mass| x.id == 2));
+        series.run(&mut *sim, 4).unwrap();
+
+        let index = read_to_string(dir.join("run.pvd")).unwrap();
+        assert!(index.contains("timestep=\"0\" group=\"\" part=\"0\" file=\"run_000000.vtp\""));
+        assert!(index.contains("timestep=\"2\" group=\"\" part=\"0\" file=\"run_000002.vtp\""));
+        assert!(index.contains("timestep=\"4\" group=\"\" part=\"0\" file=\"run_000004.vtp\""));
+        assert!(!dir.join("run_000001.vtp").exists());
+
+        let last = read_to_string(dir.join("run_000004.vtp")).unwrap();
+        assert!(last.contains("NumberOfPoints=\"1\""));
+        assert!(last.contains("format=\"ascii\">12 -4 0<"));
+        remove_dir_all(&dir).unwrap();
+    }
+}